@@ -0,0 +1,55 @@
+use anyhow::{Error, Result};
+use app::{BarAggregator, BarKind, Qty, Trade};
+use chrono::Duration;
+use csv::Writer;
+use log::{info, LevelFilter};
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::formatted_timed_builder()
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    // written by orderbook_simulator, prices in ticks and quantities in lots
+    let reader_path = "././executions/trades.csv";
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(reader_path)?;
+
+    let mut aggregators = [
+        (
+            "time",
+            BarAggregator::new(BarKind::Time(Duration::seconds(1))),
+        ),
+        ("tick", BarAggregator::new(BarKind::Tick(1_000))),
+        (
+            "volume",
+            BarAggregator::new(BarKind::Volume(Qty(1_000_000))),
+        ),
+        (
+            "dollar",
+            BarAggregator::new(BarKind::Dollar(10_000_000_000)),
+        ),
+    ];
+    let mut trades = 0;
+    for record in rdr.deserialize() {
+        let trade: Trade = record?;
+        for (_, aggregator) in aggregators.iter_mut() {
+            aggregator.push(&trade);
+        }
+        trades += 1;
+    }
+    info!("Read {trades} trades from {reader_path}");
+
+    for (name, aggregator) in aggregators.iter_mut() {
+        aggregator.flush();
+        let bars = aggregator.drain();
+        let path = format!("././executions/bars_{name}.csv");
+        let mut wtr = Writer::from_path(&path)?;
+        for bar in &bars {
+            wtr.serialize(bar)?;
+        }
+        wtr.flush()?;
+        info!("Saved {} {name} bars in {path}", bars.len());
+    }
+    Ok(())
+}
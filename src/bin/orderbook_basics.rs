@@ -1,43 +1,61 @@
-use app::{FillMetadata, OrderBook, OrderEvent, OrderType, Side};
+use app::{FillMetadata, InstrumentSpec, OrderBook, OrderEvent, OrderType, Side, TimeInForce};
 use uuid::Uuid;
 
 fn main() {
+    let spec = InstrumentSpec::default();
     let mut ob = OrderBook::default();
     let id0 = Uuid::new_v4();
     let event = ob.execute(OrderType::Market {
         id: id0,
-        qty: 1.0,
+        owner: 0,
+        qty: spec.to_qty(1.0).unwrap(),
         side: Side::Bid,
+        protection: None,
+        min_qty: None,
     });
     assert_eq!(event, OrderEvent::Unfilled { id: id0 });
 
     let id1 = Uuid::new_v4();
     let event = ob.execute(OrderType::Limit {
         id: id1,
-        price: 120.0,
-        qty: 3.0,
+        owner: 1,
+        price: spec.to_price(120.0).unwrap(),
+        qty: spec.to_qty(3.0).unwrap(),
         side: Side::Ask,
+        tif: TimeInForce::GoodTillCancel,
+        post_only: false,
+        display_qty: None,
+        min_qty: None,
+        all_or_none: false,
     });
     assert_eq!(event, OrderEvent::Placed { id: id1 });
 
     let id2 = Uuid::new_v4();
     let event = ob.execute(OrderType::Market {
         id: id2,
-        qty: 4.0,
+        owner: 2,
+        qty: spec.to_qty(4.0).unwrap(),
         side: Side::Bid,
+        protection: None,
+        min_qty: None,
     });
     assert_eq!(
         event,
-        OrderEvent::PartiallyFilled {
+        OrderEvent::PartiallyFilledCanceled {
             id: id2,
-            filled_qty: 3.0,
+            filled_qty: spec.to_qty(3.0).unwrap(),
+            canceled_qty: spec.to_qty(1.0).unwrap(),
             fills: vec![FillMetadata {
                 order_1: id2,
                 order_2: id1,
-                qty: 3.0,
-                price: 120.0,
+                owner_1: 2,
+                owner_2: 1,
+                qty: spec.to_qty(3.0).unwrap(),
+                price: spec.to_price(120.0).unwrap(),
                 taker_side: Side::Bid,
                 total_fill: true,
+                fee_1: 0.0,
+                fee_2: 0.0,
             }],
         },
     );
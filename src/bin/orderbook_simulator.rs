@@ -1,11 +1,15 @@
 use anyhow::{Error, Result};
 use app::Order;
-use app::{convert_to_order, OrderBook, OrderEvent, OrderExecution};
-use csv::{Writer};
+use app::{
+    convert_to_order, simulator_book, BarAggregator, BarKind, Exchange, InstrumentSpec, Journal,
+    OrderEvent, OrderExecution,
+};
+use chrono::{Duration, Utc};
+use csv::Writer;
 use indicatif::ProgressBar;
-use log::{info, LevelFilter};
-use std::time::Instant;
+use log::{info, warn, LevelFilter};
 use std::fs;
+use std::time::Instant;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -13,7 +17,6 @@ async fn main() -> Result<(), Error> {
         .filter_level(LevelFilter::Info)
         .init();
 
-
     fs::create_dir_all("././executions")?;
 
     let reader_path = "././order_simulations/orders.csv";
@@ -29,11 +32,25 @@ async fn main() -> Result<(), Error> {
         .has_headers(true)
         .from_path(reader_path)?;
 
-    let mut ob = OrderBook::default();
-    info!("Initialized Orderbook");
+    // must match the instrument, price and qty decimals used by generate_orders
+    let mut exchange = Exchange::new();
+    exchange.list(
+        "AAPL",
+        InstrumentSpec::from_decimals(2, 0).expect("valid decimals"),
+        simulator_book(),
+    );
+    info!("Initialized Exchange");
 
     let executions_path = "././executions/orders.csv";
     let mut wtr = Writer::from_path(executions_path)?;
+    let journal_path = "././executions/journal.bin";
+    let mut journal = Journal::create(journal_path)?;
+    let trades_path = "././executions/trades.csv";
+    let mut trades_wtr = Writer::from_path(trades_path)?;
+    let bars_path = "././executions/bars.csv";
+    let mut bars_wtr = Writer::from_path(bars_path)?;
+    let mut bars = BarAggregator::new(BarKind::Time(Duration::seconds(1)));
+    let mut last_trade_id = 0;
     let bar = ProgressBar::new(total_orders);
     info!("Executing orders and saving the executions in {executions_path}");
 
@@ -41,8 +58,31 @@ async fn main() -> Result<(), Error> {
     for msg in rdr.deserialize() {
         let begin = Instant::now();
         let order_request: Order = msg?;
-        let order = convert_to_order(&order_request);
-        let event = ob.execute(order);
+        let symbol = order_request.instrument.as_str();
+        let spec = exchange.spec(symbol).copied().unwrap_or_default();
+        let order = match convert_to_order(&order_request, &spec) {
+            Some(order) => order,
+            None => {
+                // prices and quantities the instrument cannot represent never reach the book
+                let execution = (begin.elapsed().as_nanos(), order_request, "Invalid".into());
+                wtr.serialize(OrderExecution::from(execution))?;
+                bar.inc(1);
+                continue;
+            }
+        };
+        // orders run at the time they are journaled so the run can be replayed, orders for
+        // unlisted symbols never reach a book and are not journaled
+        let timestamp = Utc::now();
+        let event = match exchange.book_mut(symbol) {
+            Some(book) => {
+                book.set_time(timestamp);
+                journal.append(timestamp, order)?;
+                let event = exchange.execute(symbol, order);
+                journal.commit(&exchange.drain_events(symbol))?;
+                event
+            }
+            None => exchange.execute(symbol, order),
+        };
         let elapsed = begin.elapsed().as_nanos();
         let status = match event {
             OrderEvent::Unfilled { id: _ } => "Unfilled".to_string(),
@@ -58,13 +98,82 @@ async fn main() -> Result<(), Error> {
                 filled_qty: _,
                 fills: _,
             } => "Filled".to_string(),
+            OrderEvent::Modified { .. } => "Modified".to_string(),
+            OrderEvent::PartiallyFilledCanceled { .. } => "PartiallyFilledCanceled".to_string(),
+            OrderEvent::Protected { .. } => "Protected".to_string(),
+            OrderEvent::Killed { .. } => "Killed".to_string(),
+            OrderEvent::Expired { .. } => "Expired".to_string(),
+            OrderEvent::Repriced { .. } => "Repriced".to_string(),
+            OrderEvent::Rejected { .. } => "Rejected".to_string(),
+            OrderEvent::Triggered { .. } => "Triggered".to_string(),
+            OrderEvent::SelfTradePrevented { .. } => "SelfTradePrevented".to_string(),
         };
+        if let Some(book) = exchange.book(symbol) {
+            // the trades of this order are the newest on the tape
+            let mut trades: Vec<_> = book
+                .recent_trades()
+                .rev()
+                .take_while(|trade| trade.id > last_trade_id)
+                .collect();
+            trades.reverse();
+            if let Some(first) = trades.first() {
+                if first.id > last_trade_id + 1 {
+                    warn!(
+                        "{} trades left the tape before they were saved",
+                        first.id - last_trade_id - 1
+                    );
+                }
+            }
+            for trade in &trades {
+                trades_wtr.serialize(trade)?;
+            }
+            bars.extend(trades.iter().copied());
+            last_trade_id = trades.last().map_or(last_trade_id, |trade| trade.id);
+        }
         wtr.serialize(OrderExecution::from((elapsed, order_request, status)))?;
         wtr.flush()?;
+        for closed in bars.drain() {
+            bars_wtr.serialize(closed)?;
+        }
         bar.inc(1);
     }
     bar.finish();
+    bars.flush();
+    for closed in bars.drain() {
+        bars_wtr.serialize(closed)?;
+    }
+    journal.flush()?;
+    info!("Journaled {} orders in {journal_path}", journal.seq());
+    trades_wtr.flush()?;
+    bars_wtr.flush()?;
+    info!("Saved the trades in {trades_path} and one second bars in {bars_path}");
+    let accounts_path = "././executions/accounts.csv";
+    let mut accounts_wtr = Writer::from_path(accounts_path)?;
+    let statements = exchange.statements();
+    for statement in &statements {
+        accounts_wtr.serialize(statement)?;
+    }
+    accounts_wtr.flush()?;
+    info!(
+        "Saved {} trader positions marked to the last trade in {accounts_path}",
+        statements.len()
+    );
+    if let (Some(best), Some(worst)) = (statements.first(), statements.last()) {
+        info!(
+            "Best trader {} with a P&L of {:.2}, worst trader {} with {:.2}",
+            best.trader, best.total_pnl, worst.trader, worst.total_pnl
+        );
+    }
     let total_elapsed = total_begin.elapsed().as_millis();
     info!("Finished execution in {total_elapsed}ms");
+    for symbol in exchange.symbols() {
+        if let (Some(stats), Some(spec)) = (exchange.stats(symbol), exchange.spec(symbol)) {
+            info!("{symbol}: {stats:?}");
+            info!(
+                "{symbol}: net fees collected {:.2}",
+                spec.amount_to_f64(stats.net_fees)
+            );
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,27 @@
+use anyhow::{Error, Result};
+use app::{replay, simulator_book, JournalReader};
+use log::{info, LevelFilter};
+use std::time::Instant;
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::formatted_timed_builder()
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    let journal_path = "././executions/journal.bin";
+    let reader = JournalReader::open(journal_path)?;
+    info!("Replaying {journal_path}");
+
+    let begin = Instant::now();
+    let replayed = replay(reader, simulator_book())?;
+    info!(
+        "Replayed {} orders, {} commands and {} events in {}ms, output hash {:016x}",
+        replayed.orders,
+        replayed.commands,
+        replayed.events.len(),
+        begin.elapsed().as_millis(),
+        replayed.hash
+    );
+    info!("{:?}", replayed.book.trade_stats());
+    Ok(())
+}
@@ -6,8 +6,31 @@ use simulator::order::{EventType, OrderKind, OrderSide};
 pub use simulator::order::{Order, OrderSimulation};
 
 mod matching_engine;
-pub use matching_engine::models::{FillMetadata, OrderEvent, OrderType, Side};
+pub use matching_engine::accounts::{AccountStatement, Accounts, Position};
+pub use matching_engine::auction::Equilibrium;
+pub use matching_engine::bars::{Bar, BarAggregator, BarKind};
+pub use matching_engine::clock::Clock;
+pub use matching_engine::exchange::{Exchange, InstrumentStats};
+pub use matching_engine::fees::{FeeEngine, FeeRate, FeeSchedule, FeeTier};
+pub use matching_engine::instrument::InstrumentSpec;
+pub use matching_engine::journal::{
+    hash_events, replay, resume, BookCommand, CommandEntry, Journal, JournalEntry, JournalError,
+    JournalReader, JournalRecord, Replay,
+};
+pub use matching_engine::market_data::{BookBuilder, MarketData, MarketDataMessage, SequenceGap};
+pub use matching_engine::matching::{Fifo, FifoProRata, MatchingPolicy, ProRata, ProRataRounding};
+pub use matching_engine::models::{
+    BookDepth, BookLevel, FillMetadata, L3Level, L3Order, L3Snapshot, LimitOrder, OrderEvent,
+    OrderType, PostOnlyMode, Price, PriceBand, Qty, RejectReason, Side, StpMode, TimeInForce,
+    Trade,
+};
 pub use matching_engine::orderbook::OrderBook;
+pub use matching_engine::session::{
+    InvalidTransition, Schedule, SessionCommand, TradingPhase, TradingSession,
+};
+pub use matching_engine::snapshot::BookSnapshot;
+pub use matching_engine::tape::{TradeStats, TradeTape};
+pub use matching_engine::triggers::StopOrder;
 use uuid::Uuid;
 
 #[derive(Serialize)]
@@ -47,30 +70,68 @@ impl From<(u128, Order, String)> for OrderExecution {
     }
 }
 
-pub fn convert_to_order(order: &Order) -> OrderType {
+/// Book listed by orderbook_simulator, a replay of its journal has to start from the same one
+pub fn simulator_book() -> OrderBook {
+    let mut ob = OrderBook::default();
+    // simulated traders pick their side at random, keep them from trading with themselves
+    ob.set_stp_mode(StpMode::CancelNewest);
+    // rates in basis points of the notional, makers earn a rebate that grows with volume
+    ob.set_fee_schedule(FeeSchedule::tiered(vec![
+        FeeTier {
+            min_volume: Qty::ZERO,
+            maker: FeeRate::BasisPoints(-0.5),
+            taker: FeeRate::BasisPoints(3.0),
+        },
+        FeeTier {
+            min_volume: Qty(10_000_000),
+            maker: FeeRate::BasisPoints(-1.0),
+            taker: FeeRate::BasisPoints(2.5),
+        },
+    ]));
+    ob
+}
+
+/// Converts a simulated order into an engine order, mapping decimal prices and quantities to
+/// ticks and lots of the given instrument. `None` if a price or quantity the order needs is
+/// not a finite number in range.
+pub fn convert_to_order(order: &Order, spec: &InstrumentSpec) -> Option<OrderType> {
     let side = match order.side {
         OrderSide::Buy => Side::Bid,
         OrderSide::Sell => Side::Ask,
     };
-    let qty = order.qty;
-    let price = order.price;
+    let qty = || spec.to_qty(order.qty);
+    let price = || spec.to_price(order.price);
     let id = order.order_id;
-    match order.event {
+    let owner = order.trader;
+    let order = match order.event {
         EventType::Cancel => OrderType::Cancel { id },
         EventType::New => match order.kind {
-            OrderKind::Market => OrderType::Market { id, qty, side },
+            OrderKind::Market => OrderType::Market {
+                id,
+                owner,
+                qty: qty()?,
+                side,
+                protection: None,
+                min_qty: None,
+            },
             OrderKind::Limit => OrderType::Limit {
                 id,
-                qty,
+                owner,
+                qty: qty()?,
                 side,
-                price,
+                price: price()?,
+                tif: TimeInForce::GoodTillCancel,
+                post_only: false,
+                display_qty: None,
+                min_qty: None,
+                all_or_none: false,
             },
         },
-        EventType::Update => OrderType::Limit {
+        EventType::Update => OrderType::Modify {
             id,
-            qty,
-            side,
-            price,
+            new_price: price()?,
+            new_qty: qty()?,
         },
-    }
+    };
+    Some(order)
 }
@@ -0,0 +1,259 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::matching_engine::instrument::InstrumentSpec;
+use crate::matching_engine::models::{FillMetadata, Price, Qty, Side};
+
+/// Holdings of one trader in one instrument. Prices are in ticks and amounts in ticks times
+/// lots, fees are included in the cash and the realized P&L.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    /// Lots held, negative when short
+    pub qty: Qty,
+    /// Average entry price of the open position, zero when flat
+    pub avg_cost: f64,
+    /// Proceeds of sales minus the cost of purchases and fees
+    pub cash: f64,
+    pub realized_pnl: f64,
+    /// Fees paid minus rebates earned
+    pub fees: f64,
+    pub volume: Qty,
+    pub trades: u64,
+}
+
+impl Position {
+    /// Books one side of a fill, an order in the opposite direction of the position closes it
+    /// at the average cost before opening the rest
+    pub fn apply(&mut self, side: Side, qty: Qty, price: Price, fee: f64) {
+        let (open, traded, price) = (self.qty.0, qty.0, price.0 as f64);
+        let signed = match side {
+            Side::Bid => traded,
+            Side::Ask => -traded,
+        };
+        self.cash -= signed as f64 * price + fee;
+        self.fees += fee;
+        self.realized_pnl -= fee;
+
+        if open == 0 || open.signum() == signed.signum() {
+            self.avg_cost = (self.avg_cost * open.abs() as f64 + price * traded as f64)
+                / (open.abs() + traded) as f64;
+        } else {
+            let closed = open.abs().min(traded);
+            self.realized_pnl += (price - self.avg_cost) * (closed * open.signum()) as f64;
+            if traded > open.abs() {
+                self.avg_cost = price;
+            } else if traded == open.abs() {
+                self.avg_cost = 0.0;
+            }
+        }
+        self.qty = Qty(open + signed);
+        self.volume += qty;
+        self.trades += 1;
+    }
+
+    /// P&L of the open position at a mark price
+    pub fn unrealized_pnl(&self, mark: Price) -> f64 {
+        (mark.0 as f64 - self.avg_cost) * self.qty.0 as f64
+    }
+
+    pub fn total_pnl(&self, mark: Price) -> f64 {
+        self.realized_pnl + self.unrealized_pnl(mark)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct InstrumentAccounts {
+    mark: Option<Price>,
+    positions: HashMap<u64, Position>,
+}
+
+/// Positions of every trader by instrument, built from fills. Open positions are marked to
+/// the last fill price of their instrument unless a mark is set.
+#[derive(Debug, Default, Clone)]
+pub struct Accounts {
+    instruments: IndexMap<String, InstrumentAccounts>,
+}
+
+impl Accounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Books both sides of a fill. Each fill has to be recorded once, auction events list a
+    /// fill on both of its orders.
+    pub fn record(&mut self, symbol: &str, fill: &FillMetadata) {
+        let instrument = match self.instruments.get_mut(symbol) {
+            Some(instrument) => instrument,
+            None => self.instruments.entry(symbol.to_string()).or_default(),
+        };
+        instrument.mark = Some(fill.price);
+        for (owner, side, fee) in [
+            (fill.owner_1, fill.taker_side, fill.fee_1),
+            (fill.owner_2, !fill.taker_side, fill.fee_2),
+        ] {
+            instrument
+                .positions
+                .entry(owner)
+                .or_default()
+                .apply(side, fill.qty, fill.price, fee);
+        }
+    }
+
+    /// Overrides the mark price of an instrument until its next fill, to value positions at a
+    /// closing or reference price
+    pub fn set_mark(&mut self, symbol: &str, price: Price) {
+        if let Some(instrument) = self.instruments.get_mut(symbol) {
+            instrument.mark = Some(price);
+        }
+    }
+
+    pub fn mark(&self, symbol: &str) -> Option<Price> {
+        self.instruments.get(symbol)?.mark
+    }
+
+    pub fn position(&self, owner: u64, symbol: &str) -> Option<&Position> {
+        self.instruments.get(symbol)?.positions.get(&owner)
+    }
+
+    /// Positions of a trader, in the order instruments first traded
+    pub fn positions(&self, owner: u64) -> impl Iterator<Item = (&str, &Position)> {
+        self.instruments
+            .iter()
+            .filter_map(move |(symbol, instrument)| {
+                Some((symbol.as_str(), instrument.positions.get(&owner)?))
+            })
+    }
+
+    /// Realized plus unrealized P&L of a position at the mark of its instrument
+    pub fn total_pnl(&self, owner: u64, symbol: &str) -> Option<f64> {
+        let instrument = self.instruments.get(symbol)?;
+        let position = instrument.positions.get(&owner)?;
+        Some(position.total_pnl(instrument.mark?))
+    }
+
+    /// One row per trader and instrument in decimal prices, quantities and money, best total
+    /// P&L first. `spec` gives the instrument of each symbol.
+    pub fn statements(&self, spec: impl Fn(&str) -> InstrumentSpec) -> Vec<AccountStatement> {
+        let mut statements: Vec<AccountStatement> = self
+            .instruments
+            .iter()
+            .flat_map(|(symbol, instrument)| {
+                let spec = spec(symbol);
+                let mark = instrument.mark.unwrap_or_default();
+                instrument
+                    .positions
+                    .iter()
+                    .map(move |(&trader, position)| AccountStatement {
+                        trader,
+                        instrument: symbol.clone(),
+                        position: spec.qty_to_f64(position.qty),
+                        avg_cost: position.avg_cost * spec.tick_size(),
+                        mark: spec.price_to_f64(mark),
+                        cash: spec.amount_to_f64(position.cash),
+                        fees: spec.amount_to_f64(position.fees),
+                        realized_pnl: spec.amount_to_f64(position.realized_pnl),
+                        unrealized_pnl: spec.amount_to_f64(position.unrealized_pnl(mark)),
+                        total_pnl: spec.amount_to_f64(position.total_pnl(mark)),
+                        volume: spec.qty_to_f64(position.volume),
+                        trades: position.trades,
+                    })
+            })
+            .collect();
+        statements.sort_by(|a, b| {
+            b.total_pnl
+                .total_cmp(&a.total_pnl)
+                .then(a.trader.cmp(&b.trader))
+                .then_with(|| a.instrument.cmp(&b.instrument))
+        });
+        statements
+    }
+}
+
+/// Position of a trader in an instrument, as written in the end of run report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountStatement {
+    pub trader: u64,
+    pub instrument: String,
+    pub position: f64,
+    pub avg_cost: f64,
+    pub mark: f64,
+    pub cash: f64,
+    pub fees: f64,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub total_pnl: f64,
+    pub volume: f64,
+    pub trades: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::test_support;
+
+    /// Fill charging 2 to the taker and paying a rebate of 1 to the maker
+    fn fill(taker: u64, maker: u64, side: Side, qty: i64, price: i64) -> FillMetadata {
+        FillMetadata {
+            fee_1: 2.0,
+            fee_2: -1.0,
+            ..test_support::fill(taker, maker, side, qty, price)
+        }
+    }
+
+    #[test]
+    fn positions_average_in_and_realize_on_the_way_out() {
+        let mut accounts = Accounts::new();
+        accounts.record("AAA", &fill(1, 2, Side::Bid, 10, 100));
+        accounts.record("AAA", &fill(1, 2, Side::Bid, 10, 110));
+        assert_eq!(accounts.position(1, "AAA").unwrap().avg_cost, 105.0);
+
+        // sells 15 of 20 then flips short 5 at 120
+        accounts.record("AAA", &fill(2, 1, Side::Bid, 5, 90));
+        accounts.record("AAA", &fill(1, 2, Side::Ask, 20, 120));
+        let long = *accounts.position(1, "AAA").unwrap();
+        assert_eq!((long.qty, long.avg_cost), (Qty(-5), 120.0));
+        assert_eq!(long.fees, 5.0);
+        assert_eq!(long.realized_pnl, -75.0 + 225.0 - 5.0);
+        assert_eq!(long.cash, -1000.0 - 1100.0 + 450.0 + 2400.0 - 5.0);
+
+        accounts.set_mark("AAA", Price(130));
+        assert_eq!(long.unrealized_pnl(Price(130)), -50.0);
+        // the cash and the marked position add up to the total P&L of both traders
+        for owner in [1, 2] {
+            let position = accounts.position(owner, "AAA").unwrap();
+            assert_eq!(
+                accounts.total_pnl(owner, "AAA"),
+                Some(position.cash + position.qty.0 as f64 * 130.0)
+            );
+        }
+        assert_eq!(accounts.positions(2).count(), 1);
+        assert_eq!(accounts.position(3, "AAA"), None);
+    }
+
+    #[test]
+    fn statements_rank_traders_in_money() {
+        let mut accounts = Accounts::new();
+        accounts.record("AAA", &fill(1, 2, Side::Bid, 10, 100));
+        accounts.record("BBB", &fill(3, 1, Side::Ask, 1, 5000));
+        accounts.set_mark("AAA", Price(120));
+
+        // ticks of 5 units of money
+        let statements = accounts.statements(|_| InstrumentSpec::new(0, 5, 0, 1).unwrap());
+        let ranking: Vec<(u64, &str, f64)> = statements
+            .iter()
+            .map(|row| (row.trader, row.instrument.as_str(), row.total_pnl))
+            .collect();
+        assert_eq!(
+            ranking,
+            vec![
+                (1, "AAA", 990.0),
+                (1, "BBB", 5.0),
+                (3, "BBB", -10.0),
+                (2, "AAA", -995.0)
+            ]
+        );
+        assert_eq!(statements[0].avg_cost, 500.0);
+        assert_eq!(statements[0].mark, 600.0);
+    }
+}
@@ -1,39 +1,223 @@
-use crate::matching_engine::models::LimitOrder;
-use indexmap::IndexMap;
+use crate::matching_engine::models::{LimitOrder, Qty};
+use std::collections::HashMap;
 use std::ops::{Index, IndexMut};
 use uuid::Uuid;
 
+/// Orders of a price level in time priority, linked through the `prev` and `next` handles of
+/// their arena slots. The level of an order is found from its side and price in O(log n),
+/// the order is then unlinked in O(1).
+///
+/// The level also keeps the total quantity and number of its orders, the arena updates them
+/// whenever an order is linked, unlinked or changed through `OrderArena::update`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct LevelQueue {
+    head: Option<usize>,
+    tail: Option<usize>,
+    qty: Qty,
+    hidden_qty: Qty,
+    len: usize,
+}
+
+impl LevelQueue {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Number of orders
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Displayed quantity
+    #[inline(always)]
+    pub fn qty(&self) -> Qty {
+        self.qty
+    }
+
+    /// Displayed and hidden quantity
+    #[inline(always)]
+    pub fn open_qty(&self) -> Qty {
+        self.qty + self.hidden_qty
+    }
+
+    /// Displayed quantity and number of orders, `None` for an empty level
+    pub fn state(&self) -> Option<(Qty, usize)> {
+        match self.is_empty() {
+            true => None,
+            false => Some((self.qty, self.len)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    order: LimitOrder,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Slab of resting orders. Orders are addressed by slot index inside the engine and by id
+/// from the outside. Slots of deleted orders are reused, so memory only grows with the
+/// number of orders resting at the same time.
 #[derive(Debug)]
 pub struct OrderArena {
-    order_map: IndexMap<Uuid, LimitOrder>,
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    index: HashMap<Uuid, usize>,
+    max_orders: Option<usize>,
 }
 
 impl OrderArena {
     pub fn new(capacity: usize) -> Self {
         Self {
-            order_map: IndexMap::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            index: HashMap::with_capacity(capacity),
+            max_orders: None,
         }
     }
 
-    pub fn get(&self, id: Uuid) -> Option<(f64, usize)> {
-        self.order_map
-            .get_full(&id)
-            .map(|(index, _key, order)| (order.price, index))
+    /// Drops every order, keeping the allocated memory
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.index.clear();
+    }
+
+    #[inline(always)]
+    pub fn max_orders(&self) -> Option<usize> {
+        self.max_orders
+    }
+
+    /// Limits the number of open orders, unbounded by default
+    pub fn set_max_orders(&mut self, max_orders: Option<usize>) {
+        self.max_orders = max_orders;
+    }
+
+    /// Number of open orders
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        matches!(self.max_orders, Some(max_orders) if self.index.len() >= max_orders)
+    }
+
+    /// Returns the index of an order that is still open
+    pub fn get(&self, id: Uuid) -> Option<usize> {
+        self.index.get(&id).copied()
     }
 
-    pub fn insert(&mut self, id: Uuid, price: f64, qty: f64) -> usize {
-        let (index, _limit_order) = self
-            .order_map
-            .insert_full(id, LimitOrder { id, price, qty });
+    /// Stores an order outside of any level, see `push_back`. The caller checks `is_full`
+    /// first.
+    pub fn insert(&mut self, order: LimitOrder) -> usize {
+        let id = order.id;
+        let slot = Slot {
+            order,
+            prev: None,
+            next: None,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(id, index);
         index
     }
 
+    /// Drops an order that is no longer linked to a level, its slot is reused by the next
+    /// insert
     pub fn delete(&mut self, key: &Uuid) -> bool {
-        if let Some((_index, _key, order)) = self.order_map.get_full_mut(key) {
-            order.qty = 0.0;
-            return true;
+        match self.index.remove(key) {
+            Some(index) => {
+                let order = &mut self.slots[index].order;
+                order.qty = Qty::ZERO;
+                order.hidden_qty = Qty::ZERO;
+                self.free.push(index);
+                true
+            }
+            None => false,
         }
-        false
+    }
+
+    /// Appends an order to the back of a level
+    pub fn push_back(&mut self, queue: &mut LevelQueue, index: usize) {
+        self.slots[index].prev = queue.tail;
+        self.slots[index].next = None;
+        match queue.tail {
+            Some(tail) => self.slots[tail].next = Some(index),
+            None => queue.head = Some(index),
+        }
+        queue.tail = Some(index);
+        queue.qty += self.slots[index].order.qty;
+        queue.hidden_qty += self.slots[index].order.hidden_qty;
+        queue.len += 1;
+    }
+
+    /// Removes an order from the level it is linked to in O(1)
+    pub fn unlink(&mut self, queue: &mut LevelQueue, index: usize) {
+        let (prev, next) = (self.slots[index].prev, self.slots[index].next);
+        match prev {
+            Some(prev) => self.slots[prev].next = next,
+            None => queue.head = next,
+        }
+        match next {
+            Some(next) => self.slots[next].prev = prev,
+            None => queue.tail = prev,
+        }
+        self.slots[index].prev = None;
+        self.slots[index].next = None;
+        queue.qty -= self.slots[index].order.qty;
+        queue.hidden_qty -= self.slots[index].order.hidden_qty;
+        queue.len -= 1;
+    }
+
+    /// Changes an order linked to `queue`, keeping the totals of the level up to date
+    pub fn update<R>(
+        &mut self,
+        queue: &mut LevelQueue,
+        index: usize,
+        f: impl FnOnce(&mut LimitOrder) -> R,
+    ) -> R {
+        let order = &mut self.slots[index].order;
+        let (qty, hidden_qty) = (order.qty, order.hidden_qty);
+        let result = f(order);
+        queue.qty = queue.qty - qty + order.qty;
+        queue.hidden_qty = queue.hidden_qty - hidden_qty + order.hidden_qty;
+        result
+    }
+
+    /// Indices of the orders of a level, oldest first
+    pub fn iter<'a>(&'a self, queue: &LevelQueue) -> LevelIter<'a> {
+        LevelIter {
+            arena: self,
+            next: queue.head,
+        }
+    }
+}
+
+pub struct LevelIter<'a> {
+    arena: &'a OrderArena,
+    next: Option<usize>,
+}
+
+impl<'a> Iterator for LevelIter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let index = self.next?;
+        self.next = self.arena.slots[index].next;
+        Some(index)
     }
 }
 
@@ -42,15 +226,54 @@ impl Index<usize> for OrderArena {
 
     #[inline]
     fn index(&self, index: usize) -> &LimitOrder {
-        let (_key, order) = self.order_map.get_index(index).unwrap();
-        order
+        &self.slots[index].order
     }
 }
 
 impl IndexMut<usize> for OrderArena {
     #[inline]
     fn index_mut(&mut self, index: usize) -> &mut LimitOrder {
-        let (_key, order) = self.order_map.get_index_mut(index).unwrap();
-        order
+        &mut self.slots[index].order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::{Price, Side, TimeInForce};
+
+    fn order() -> LimitOrder {
+        LimitOrder::new(
+            Uuid::new_v4(),
+            0,
+            Side::Bid,
+            Price(100),
+            Qty(1),
+            TimeInForce::GoodTillCancel,
+            None,
+        )
+    }
+
+    #[test]
+    fn reuses_slots_of_deleted_orders() {
+        let mut arena = OrderArena::new(2);
+        arena.set_max_orders(Some(2));
+        let (first, second) = (order(), order());
+        let first_id = first.id;
+        let first_idx = arena.insert(first);
+        arena.insert(second);
+        assert!(arena.is_full());
+
+        assert!(arena.delete(&first_id));
+        assert!(!arena.delete(&first_id));
+        assert_eq!(arena.get(first_id), None);
+        assert!(!arena.is_full());
+
+        let third = order();
+        let third_id = third.id;
+        assert_eq!(arena.insert(third), first_idx);
+        assert_eq!(arena.get(third_id), Some(first_idx));
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena.slots.len(), 2);
     }
 }
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::models::{Price, Qty};
+
+/// Single price at which a call auction uncrosses
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Equilibrium {
+    pub price: Price,
+    /// Quantity executable at `price`
+    pub volume: Qty,
+    /// Bid quantity minus ask quantity willing to trade at `price`
+    pub imbalance: Qty,
+}
+
+/// Finds the uncrossing price of an auction book.
+/// `bids` and `asks` hold the total quantity of each price level, in any order.
+///
+/// The price maximizes the executable volume. Ties are broken by the smallest absolute
+/// imbalance, then by the distance to `reference_price`, then by the lowest price.
+pub fn equilibrium(
+    bids: &[(Price, Qty)],
+    asks: &[(Price, Qty)],
+    reference_price: Option<Price>,
+) -> Option<Equilibrium> {
+    let mut candidates: Vec<Price> = bids.iter().chain(asks).map(|(price, _)| *price).collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let mut best: Option<Equilibrium> = None;
+    for price in candidates {
+        let bid_qty = bids
+            .iter()
+            .filter(|(bid, _)| *bid >= price)
+            .fold(Qty::ZERO, |acc, (_, qty)| acc + *qty);
+        let ask_qty = asks
+            .iter()
+            .filter(|(ask, _)| *ask <= price)
+            .fold(Qty::ZERO, |acc, (_, qty)| acc + *qty);
+        let candidate = Equilibrium {
+            price,
+            volume: bid_qty.min(ask_qty),
+            imbalance: bid_qty - ask_qty,
+        };
+        if candidate.volume.is_zero() {
+            continue;
+        }
+        best = match best {
+            Some(current) if !is_better(&candidate, &current, reference_price) => Some(current),
+            _ => Some(candidate),
+        };
+    }
+    best
+}
+
+fn is_better(
+    candidate: &Equilibrium,
+    current: &Equilibrium,
+    reference_price: Option<Price>,
+) -> bool {
+    if candidate.volume != current.volume {
+        return candidate.volume > current.volume;
+    }
+    if candidate.imbalance.0.abs() != current.imbalance.0.abs() {
+        return candidate.imbalance.0.abs() < current.imbalance.0.abs();
+    }
+    match reference_price {
+        Some(reference) => {
+            (candidate.price - reference).0.abs() < (current.price - reference).0.abs()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(i64, i64)]) -> Vec<(Price, Qty)> {
+        levels.iter().map(|(p, q)| (Price(*p), Qty(*q))).collect()
+    }
+
+    #[test]
+    fn maximizes_volume_then_minimizes_imbalance() {
+        let bids = levels(&[(102, 10), (101, 10), (100, 10)]);
+        let asks = levels(&[(99, 5), (100, 10), (101, 20)]);
+        let eq = equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(eq.price, Price(101));
+        assert_eq!(eq.volume, Qty(20));
+        assert_eq!(eq.imbalance, Qty(-15));
+        assert_eq!(
+            equilibrium(&levels(&[(99, 1)]), &levels(&[(100, 1)]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn reference_price_breaks_ties() {
+        let bids = levels(&[(102, 10)]);
+        let asks = levels(&[(100, 10)]);
+        assert_eq!(equilibrium(&bids, &asks, None).unwrap().price, Price(100));
+        assert_eq!(
+            equilibrium(&bids, &asks, Some(Price(105))).unwrap().price,
+            Price(102)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::models::{Price, Qty, Trade};
+
+/// When a bar closes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BarKind {
+    /// Fixed intervals aligned to the Unix epoch, intervals without trades produce no bar.
+    /// The duration must be positive.
+    Time(Duration),
+    /// Every n trades
+    Tick(u64),
+    /// Once the volume reaches the threshold
+    Volume(Qty),
+    /// Once the notional reaches the threshold, in ticks times lots. Divide an amount of
+    /// money by `tick_size * lot_size` of the instrument to get it.
+    Dollar(i128),
+}
+
+/// Open, high, low, close, volume and VWAP of consecutive trades. Prices are in ticks and
+/// volumes in lots.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    /// Start of the interval for time bars, time of the first trade otherwise
+    pub start: DateTime<Utc>,
+    /// Time of the last trade
+    pub end: DateTime<Utc>,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Qty,
+    pub vwap: f64,
+    pub trades: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct OpenBar {
+    bar: Bar,
+    notional: i128,
+}
+
+impl OpenBar {
+    fn new(start: DateTime<Utc>, trade: &Trade) -> Self {
+        Self {
+            bar: Bar {
+                start,
+                end: trade.timestamp,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                volume: Qty::ZERO,
+                vwap: 0.0,
+                trades: 0,
+            },
+            notional: 0,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        let bar = &mut self.bar;
+        bar.end = trade.timestamp;
+        bar.high = bar.high.max(trade.price);
+        bar.low = bar.low.min(trade.price);
+        bar.close = trade.price;
+        bar.volume += trade.qty;
+        bar.trades += 1;
+        self.notional += trade.price.0 as i128 * trade.qty.0 as i128;
+    }
+
+    fn close(mut self) -> Bar {
+        self.bar.vwap = self.notional as f64 / self.bar.volume.0 as f64;
+        self.bar
+    }
+}
+
+/// Builds bars from a stream of trades, live from `OrderBook::recent_trades` or offline from
+/// a trades file. Trades already seen, by trade id, are skipped, so the recent trades of a
+/// book can be pushed after every call.
+#[derive(Debug)]
+pub struct BarAggregator {
+    kind: BarKind,
+    last_id: u64,
+    open: Option<OpenBar>,
+    bars: Vec<Bar>,
+}
+
+impl BarAggregator {
+    pub fn new(kind: BarKind) -> Self {
+        Self {
+            kind,
+            last_id: 0,
+            open: None,
+            bars: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn kind(&self) -> BarKind {
+        self.kind
+    }
+
+    pub fn push(&mut self, trade: &Trade) {
+        if trade.id <= self.last_id {
+            return;
+        }
+        self.last_id = trade.id;
+
+        let start = match self.kind {
+            BarKind::Time(duration) => trade
+                .timestamp
+                .duration_trunc(duration)
+                .unwrap_or(trade.timestamp),
+            _ => trade.timestamp,
+        };
+        // a trade in a later interval closes the time bar before it
+        if let Some(open) = self.open {
+            if matches!(self.kind, BarKind::Time(_)) && open.bar.start != start {
+                self.bars.push(open.close());
+                self.open = None;
+            }
+        }
+        let open = self.open.get_or_insert_with(|| OpenBar::new(start, trade));
+        open.add(trade);
+
+        let full = match self.kind {
+            BarKind::Time(_) => false,
+            BarKind::Tick(trades) => open.bar.trades >= trades,
+            BarKind::Volume(volume) => open.bar.volume >= volume,
+            BarKind::Dollar(notional) => open.notional >= notional,
+        };
+        if full {
+            self.bars.push(open.close());
+            self.open = None;
+        }
+    }
+
+    pub fn extend<'a>(&mut self, trades: impl IntoIterator<Item = &'a Trade>) {
+        for trade in trades {
+            self.push(trade);
+        }
+    }
+
+    /// Bar still collecting trades
+    pub fn current(&self) -> Option<Bar> {
+        self.open.map(OpenBar::close)
+    }
+
+    /// Closes the current bar, at the end of a session or of a file
+    pub fn flush(&mut self) {
+        if let Some(open) = self.open.take() {
+            self.bars.push(open.close());
+        }
+    }
+
+    /// Takes the bars closed since the last call, oldest first
+    pub fn drain(&mut self) -> Vec<Bar> {
+        std::mem::take(&mut self.bars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::Side;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn trades(prints: &[(u32, i64, i64)]) -> Vec<Trade> {
+        prints
+            .iter()
+            .enumerate()
+            .map(|(i, &(second, price, qty))| Trade {
+                id: i as u64 + 1,
+                price: Price(price),
+                qty: Qty(qty),
+                aggressor: Side::Bid,
+                taker: Uuid::new_v4(),
+                maker: Uuid::new_v4(),
+                timestamp: Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, second).unwrap(),
+            })
+            .collect()
+    }
+
+    fn ohlcv(bars: &[Bar]) -> Vec<(i64, i64, i64, i64, i64, u64)> {
+        bars.iter()
+            .map(|bar| {
+                (
+                    bar.open.0,
+                    bar.high.0,
+                    bar.low.0,
+                    bar.close.0,
+                    bar.volume.0,
+                    bar.trades,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn time_bars_follow_the_clock() {
+        let prints = trades(&[(1, 100, 1), (9, 103, 2), (12, 99, 1), (45, 101, 4)]);
+        let mut bars = BarAggregator::new(BarKind::Time(Duration::seconds(10)));
+        bars.extend(&prints);
+        // trades already seen are skipped
+        bars.extend(&prints[2..]);
+        bars.flush();
+
+        let bars = bars.drain();
+        assert_eq!(
+            ohlcv(&bars),
+            vec![
+                (100, 103, 100, 103, 3, 2),
+                (99, 99, 99, 99, 1, 1),
+                (101, 101, 101, 101, 4, 1)
+            ]
+        );
+        assert_eq!(bars[1].start, prints[0].timestamp + Duration::seconds(9));
+        assert_eq!(bars[0].end, prints[1].timestamp);
+        assert_eq!(bars[0].vwap, 102.0);
+    }
+
+    #[test]
+    fn activity_bars_close_on_thresholds() {
+        let prints = trades(&[(1, 100, 1), (2, 102, 3), (3, 101, 2), (4, 104, 1)]);
+        let mut ticks = BarAggregator::new(BarKind::Tick(3));
+        let mut volume = BarAggregator::new(BarKind::Volume(Qty(4)));
+        let mut dollars = BarAggregator::new(BarKind::Dollar(300));
+        for bars in [&mut ticks, &mut volume, &mut dollars] {
+            bars.extend(&prints);
+        }
+
+        assert_eq!(ohlcv(&ticks.drain()), vec![(100, 102, 100, 101, 6, 3)]);
+        assert_eq!(ticks.current().unwrap().close, Price(104));
+        assert_eq!(ohlcv(&volume.drain()), vec![(100, 102, 100, 102, 4, 2)]);
+        assert_eq!(
+            ohlcv(&dollars.drain()),
+            vec![(100, 102, 100, 102, 4, 2), (101, 104, 101, 104, 3, 2)]
+        );
+        assert_eq!(dollars.current(), None);
+    }
+}
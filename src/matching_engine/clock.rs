@@ -0,0 +1,21 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Source of the timestamps the book puts on resting orders
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Clock {
+    /// Wall clock time
+    #[default]
+    System,
+    /// Simulated time, only moves when it is set
+    Manual(DateTime<Utc>),
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Manual(now) => *now,
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::matching_engine::accounts::{AccountStatement, Accounts};
+use crate::matching_engine::instrument::InstrumentSpec;
+use crate::matching_engine::models::{OrderEvent, OrderType, Price, Qty, RejectReason};
+use crate::matching_engine::orderbook::OrderBook;
+
+/// Counters kept for every listed instrument
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize)]
+pub struct InstrumentStats {
+    /// Orders routed to the book, rejected ones included
+    pub orders: u64,
+    pub rejected: u64,
+    /// Fills the instrument traded, one per resting order hit
+    pub fills: u64,
+    pub traded_volume: Qty,
+    pub last_price: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    /// Fees charged minus rebates paid, in ticks times lots
+    pub net_fees: f64,
+}
+
+#[derive(Debug)]
+struct Listing {
+    spec: InstrumentSpec,
+    book: OrderBook,
+    orders: u64,
+    rejected: u64,
+    fills: u64,
+    net_fees: f64,
+    events: Vec<OrderEvent>,
+}
+
+impl Listing {
+    /// Appends events to the stream of the instrument and books their fills. An auction fill
+    /// is listed on both of its orders, it is only counted on the event of its taker.
+    fn record(&mut self, accounts: &mut Accounts, symbol: &str, events: &[OrderEvent]) {
+        for event in events {
+            for fill in event
+                .fills()
+                .iter()
+                .filter(|fill| fill.order_1 == event.id())
+            {
+                self.fills += 1;
+                self.net_fees += fill.fee_1 + fill.fee_2;
+                accounts.record(symbol, fill);
+            }
+        }
+        self.events.extend_from_slice(events);
+    }
+}
+
+/// Registry of instruments, each with its own book, routing orders by symbol. Fills are
+/// booked on the accounts of both traders.
+#[derive(Debug, Default)]
+pub struct Exchange {
+    listings: IndexMap<String, Listing>,
+    accounts: Accounts,
+}
+
+impl Exchange {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lists an instrument, returns false if the symbol is already listed
+    pub fn list(
+        &mut self,
+        symbol: impl Into<String>,
+        spec: InstrumentSpec,
+        book: OrderBook,
+    ) -> bool {
+        let symbol = symbol.into();
+        if self.listings.contains_key(&symbol) {
+            return false;
+        }
+        self.listings.insert(
+            symbol,
+            Listing {
+                spec,
+                book,
+                orders: 0,
+                rejected: 0,
+                fills: 0,
+                net_fees: 0.0,
+                events: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// Listed symbols, in listing order
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.listings.keys().map(|symbol| symbol.as_str())
+    }
+
+    pub fn spec(&self, symbol: &str) -> Option<&InstrumentSpec> {
+        self.listings.get(symbol).map(|listing| &listing.spec)
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.listings.get(symbol).map(|listing| &listing.book)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.listings
+            .get_mut(symbol)
+            .map(|listing| &mut listing.book)
+    }
+
+    /// Sends an order to the book of `symbol`, orders for unknown symbols are rejected.
+    /// The event and any cascade it caused are also appended to the event stream of the
+    /// instrument.
+    pub fn execute(&mut self, symbol: &str, order: OrderType) -> OrderEvent {
+        let listing = match self.listings.get_mut(symbol) {
+            Some(listing) => listing,
+            None => {
+                return OrderEvent::Rejected {
+                    id: order.id(),
+                    reason: RejectReason::UnknownInstrument,
+                }
+            }
+        };
+        let event = listing.book.execute(order);
+        listing.orders += 1;
+        if let OrderEvent::Rejected { .. } = event {
+            listing.rejected += 1;
+        }
+        listing.record(&mut self.accounts, symbol, std::slice::from_ref(&event));
+        let cascade = listing.book.cascade().to_vec();
+        listing.record(&mut self.accounts, symbol, &cascade);
+        event
+    }
+
+    /// Starts a call auction on the book of `symbol`, returns false if it is not listed
+    pub fn begin_auction(&mut self, symbol: &str) -> bool {
+        match self.listings.get_mut(symbol) {
+            Some(listing) => {
+                listing.book.begin_auction();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ends the auction of `symbol`, see `OrderBook::uncross`
+    pub fn uncross(&mut self, symbol: &str, reference_price: Option<Price>) -> Vec<OrderEvent> {
+        self.route(symbol, |book| book.uncross(reference_price))
+    }
+
+    /// Expires the good-till-date orders of `symbol` due at `now`
+    pub fn expire(&mut self, symbol: &str, now: DateTime<Utc>) -> Vec<OrderEvent> {
+        self.route(symbol, |book| book.expire(now))
+    }
+
+    /// Expires the day orders of `symbol` at the end of its session
+    pub fn expire_day_orders(&mut self, symbol: &str) -> Vec<OrderEvent> {
+        self.route(symbol, OrderBook::expire_day_orders)
+    }
+
+    /// Runs a book operation that is not an order and appends its events to the event
+    /// stream of the instrument. Unknown symbols have no events.
+    fn route(
+        &mut self,
+        symbol: &str,
+        f: impl FnOnce(&mut OrderBook) -> Vec<OrderEvent>,
+    ) -> Vec<OrderEvent> {
+        let listing = match self.listings.get_mut(symbol) {
+            Some(listing) => listing,
+            None => return Vec::new(),
+        };
+        let events = f(&mut listing.book);
+        listing.record(&mut self.accounts, symbol, &events);
+        events
+    }
+
+    /// Takes the events the instrument produced since the last call
+    pub fn drain_events(&mut self, symbol: &str) -> Vec<OrderEvent> {
+        match self.listings.get_mut(symbol) {
+            Some(listing) => std::mem::take(&mut listing.events),
+            None => Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// To set the marks of open positions
+    #[inline(always)]
+    pub fn accounts_mut(&mut self) -> &mut Accounts {
+        &mut self.accounts
+    }
+
+    /// Positions of every trader in decimal units, best total P&L first
+    pub fn statements(&self) -> Vec<AccountStatement> {
+        self.accounts
+            .statements(|symbol| self.spec(symbol).copied().unwrap_or_default())
+    }
+
+    pub fn stats(&self, symbol: &str) -> Option<InstrumentStats> {
+        self.listings.get(symbol).map(|listing| InstrumentStats {
+            orders: listing.orders,
+            rejected: listing.rejected,
+            fills: listing.fills,
+            traded_volume: listing.book.traded_volume(),
+            last_price: listing.book.trade_stats().last,
+            best_bid: listing.book.best_bid(),
+            best_ask: listing.book.best_ask(),
+            net_fees: listing.net_fees,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::{Side, TimeInForce};
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use uuid::Uuid;
+
+    #[test]
+    fn routes_orders_by_symbol() {
+        let mut exchange = Exchange::new();
+        assert!(exchange.list("AAA", InstrumentSpec::default(), OrderBook::default()));
+        assert!(exchange.list("BBB", InstrumentSpec::default(), OrderBook::default()));
+        assert!(!exchange.list("AAA", InstrumentSpec::default(), OrderBook::default()));
+
+        exchange.execute("AAA", limit(Uuid::new_v4(), Side::Ask, 10, 100));
+        exchange.execute("BBB", limit(Uuid::new_v4(), Side::Ask, 10, 200));
+        exchange.execute("AAA", limit(Uuid::new_v4(), Side::Bid, 4, 100));
+        assert!(matches!(
+            exchange.execute("CCC", limit(Uuid::new_v4(), Side::Bid, 4, 100)),
+            OrderEvent::Rejected {
+                reason: RejectReason::UnknownInstrument,
+                ..
+            }
+        ));
+
+        let stats = exchange.stats("AAA").unwrap();
+        assert_eq!(stats.orders, 2);
+        assert_eq!(stats.fills, 1);
+        assert_eq!(stats.traded_volume, Qty(4));
+        assert_eq!(stats.last_price, Some(Price(100)));
+        assert_eq!(stats.best_ask, Some(Price(100)));
+        assert_eq!(exchange.stats("BBB").unwrap().traded_volume, Qty::ZERO);
+        assert_eq!(exchange.stats("CCC"), None);
+
+        // both sides of the fill belong to owner 0
+        let trader = exchange.accounts().position(0, "AAA").unwrap();
+        assert_eq!((trader.qty, trader.trades), (Qty::ZERO, 2));
+        assert_eq!(exchange.statements().len(), 1);
+
+        assert_eq!(exchange.drain_events("AAA").len(), 2);
+        assert!(exchange.drain_events("AAA").is_empty());
+    }
+
+    #[test]
+    fn auctions_and_expiries_reach_the_event_stream() {
+        let mut exchange = Exchange::new();
+        exchange.list("AAA", InstrumentSpec::default(), OrderBook::default());
+        assert!(exchange.begin_auction("AAA"));
+        assert!(!exchange.begin_auction("CCC"));
+        exchange.execute("AAA", limit(Uuid::new_v4(), Side::Ask, 10, 100));
+        exchange.execute("AAA", limit(Uuid::new_v4(), Side::Bid, 4, 101));
+        exchange.drain_events("AAA");
+
+        let events = exchange.uncross("AAA", None);
+        assert_eq!(events.len(), 2);
+        assert_eq!(exchange.drain_events("AAA"), events);
+        // the fill is listed on both orders and booked once
+        let stats = exchange.stats("AAA").unwrap();
+        assert_eq!((stats.fills, stats.traded_volume), (1, Qty(4)));
+        let trader = exchange.accounts().position(0, "AAA").unwrap();
+        assert_eq!((trader.volume, trader.trades), (Qty(8), 2));
+        assert!(exchange.uncross("CCC", None).is_empty());
+
+        let day = limit(Uuid::new_v4(), Side::Bid, 1, 90).tif(TimeInForce::Day);
+        exchange.execute("AAA", day);
+        exchange.drain_events("AAA");
+        assert!(exchange.expire("AAA", Utc::now()).is_empty());
+        let expired = exchange.expire_day_orders("AAA");
+        assert_eq!(
+            expired,
+            vec![OrderEvent::Expired {
+                id: day.id(),
+                qty: Qty(1)
+            }]
+        );
+        assert_eq!(exchange.drain_events("AAA"), expired);
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::matching_engine::models::{FillMetadata, Qty};
+
+/// Days of trading counted in the volume of a trader
+const VOLUME_WINDOW_DAYS: i64 = 30;
+
+/// Fee on one side of a fill, negative rates pay a rebate
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FeeRate {
+    /// Share of the notional, 1 basis point being 0.01%
+    BasisPoints(f64),
+    /// Amount per lot traded, in ticks
+    PerLot(f64),
+}
+
+impl Default for FeeRate {
+    fn default() -> Self {
+        FeeRate::BasisPoints(0.0)
+    }
+}
+
+impl FeeRate {
+    /// Fee of a fill in ticks times lots
+    pub fn fee(&self, fill: &FillMetadata) -> f64 {
+        match *self {
+            FeeRate::BasisPoints(bps) => bps * fill.price.0 as f64 * fill.qty.0 as f64 / 10_000.0,
+            FeeRate::PerLot(amount) => amount * fill.qty.0 as f64,
+        }
+    }
+}
+
+/// Rates paid by traders who traded at least `min_volume` lots over the last 30 days
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: Qty,
+    pub maker: FeeRate,
+    pub taker: FeeRate,
+}
+
+/// Maker and taker rates of an instrument, by volume tier
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    /// Sorted by minimum volume
+    tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    /// Same rates for every trader
+    pub fn flat(maker: FeeRate, taker: FeeRate) -> Self {
+        Self::tiered(vec![FeeTier {
+            min_volume: Qty::ZERO,
+            maker,
+            taker,
+        }])
+    }
+
+    /// Traders below the lowest tier pay no fees
+    pub fn tiered(mut tiers: Vec<FeeTier>) -> Self {
+        tiers.sort_by_key(|tier| tier.min_volume);
+        Self { tiers }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// Highest tier reached by a 30-day volume
+    pub fn tier(&self, volume: Qty) -> Option<&FeeTier> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume <= volume)
+    }
+}
+
+/// Charges fills according to a schedule and keeps the daily volume of every trader
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeEngine {
+    schedule: FeeSchedule,
+    /// Lots traded by each owner per day, oldest day first
+    volumes: HashMap<u64, VecDeque<(NaiveDate, Qty)>>,
+}
+
+impl FeeEngine {
+    pub fn new(schedule: FeeSchedule) -> Self {
+        Self {
+            schedule,
+            volumes: HashMap::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn schedule(&self) -> &FeeSchedule {
+        &self.schedule
+    }
+
+    /// Replaces the rates, the volumes traded so far are kept
+    pub fn set_schedule(&mut self, schedule: FeeSchedule) {
+        self.schedule = schedule;
+    }
+
+    /// Lots traded by an owner over the last 30 days, today included
+    pub fn volume(&self, owner: u64, now: DateTime<Utc>) -> Qty {
+        let since = now.date_naive() - Duration::days(VOLUME_WINDOW_DAYS);
+        self.volumes.get(&owner).map_or(Qty::ZERO, |days| {
+            days.iter()
+                .filter(|(day, _)| *day > since)
+                .fold(Qty::ZERO, |total, (_, qty)| total + *qty)
+        })
+    }
+
+    /// Sets the fees of both sides of a fill, at the tier each owner reached before it, then
+    /// adds the fill to their volume. Volumes are only counted while a schedule is set.
+    pub fn charge(&mut self, fill: &mut FillMetadata, now: DateTime<Utc>) {
+        if self.schedule.is_empty() {
+            return;
+        }
+        let taker = self.schedule.tier(self.volume(fill.owner_1, now));
+        fill.fee_1 = taker.map_or(0.0, |tier| tier.taker.fee(fill));
+        let maker = self.schedule.tier(self.volume(fill.owner_2, now));
+        fill.fee_2 = maker.map_or(0.0, |tier| tier.maker.fee(fill));
+
+        let today = now.date_naive();
+        let since = today - Duration::days(VOLUME_WINDOW_DAYS);
+        for owner in [fill.owner_1, fill.owner_2] {
+            let days = self.volumes.entry(owner).or_default();
+            while days.front().is_some_and(|(day, _)| *day <= since) {
+                days.pop_front();
+            }
+            match days.back_mut() {
+                Some((day, qty)) if *day == today => *qty += fill.qty,
+                _ => days.push_back((today, fill.qty)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::Side;
+    use crate::matching_engine::test_support::fill;
+    use chrono::TimeZone;
+
+    #[test]
+    fn tiers_follow_the_rolling_volume() {
+        let mut fees = FeeEngine::new(FeeSchedule::tiered(vec![
+            FeeTier {
+                min_volume: Qty(100),
+                maker: FeeRate::BasisPoints(-2.0),
+                taker: FeeRate::PerLot(0.5),
+            },
+            FeeTier {
+                min_volume: Qty::ZERO,
+                maker: FeeRate::BasisPoints(-1.0),
+                taker: FeeRate::BasisPoints(5.0),
+            },
+        ]));
+        let day = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+
+        let mut first = fill(1, 2, Side::Bid, 100, 10_000);
+        fees.charge(&mut first, day);
+        assert_eq!((first.fee_1, first.fee_2), (500.0, -100.0));
+
+        // both owners reached the upper tier, the maker side now trades as taker
+        let mut second = fill(2, 1, Side::Bid, 10, 10_000);
+        fees.charge(&mut second, day + Duration::days(29));
+        assert_eq!((second.fee_1, second.fee_2), (5.0, -20.0));
+        assert_eq!(fees.volume(1, day + Duration::days(29)), Qty(110));
+
+        // the first fill left the window
+        let mut third = fill(1, 2, Side::Bid, 10, 10_000);
+        fees.charge(&mut third, day + Duration::days(30));
+        assert_eq!((third.fee_1, third.fee_2), (50.0, -10.0));
+        assert_eq!(fees.volume(2, day + Duration::days(30)), Qty(20));
+    }
+
+    #[test]
+    fn no_schedule_charges_nothing() {
+        let mut fees = FeeEngine::default();
+        let mut free = fill(1, 2, Side::Bid, 10, 100);
+        fees.charge(&mut free, Utc::now());
+        assert_eq!((free.fee_1, free.fee_2), (0.0, 0.0));
+        assert_eq!(fees.volume(1, Utc::now()), Qty::ZERO);
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::models::{Price, Qty};
+
+/// Tick and lot size of an instrument.
+///
+/// Sizes are given as an integer number of units of `10^-decimals`, so a tick of 0.05 is
+/// `price_decimals: 2, tick: 5`. Decimal prices and quantities are only used at the API edge,
+/// the engine itself works on `Price` (ticks) and `Qty` (lots).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstrumentSpec {
+    pub price_decimals: u32,
+    pub tick: i64,
+    pub qty_decimals: u32,
+    pub lot: i64,
+}
+
+impl Default for InstrumentSpec {
+    fn default() -> Self {
+        Self {
+            price_decimals: 2,
+            tick: 1,
+            qty_decimals: 0,
+            lot: 1,
+        }
+    }
+}
+
+impl InstrumentSpec {
+    /// Most decimals of a price or quantity, `10^18` being the largest power of ten in an `i64`
+    pub const MAX_DECIMALS: u32 = 18;
+
+    /// `None` if the tick or the lot size is not positive, or if there are more than
+    /// `MAX_DECIMALS` decimals
+    pub fn new(price_decimals: u32, tick: i64, qty_decimals: u32, lot: i64) -> Option<Self> {
+        if tick <= 0 || lot <= 0 || price_decimals.max(qty_decimals) > Self::MAX_DECIMALS {
+            return None;
+        }
+        Some(Self {
+            price_decimals,
+            tick,
+            qty_decimals,
+            lot,
+        })
+    }
+
+    /// Spec with a tick of `10^-price_decimals` and a lot of `10^-qty_decimals`
+    pub fn from_decimals(price_decimals: u32, qty_decimals: u32) -> Option<Self> {
+        Self::new(price_decimals, 1, qty_decimals, 1)
+    }
+
+    pub fn tick_size(&self) -> f64 {
+        self.tick as f64 / 10_i64.pow(self.price_decimals) as f64
+    }
+
+    pub fn lot_size(&self) -> f64 {
+        self.lot as f64 / 10_i64.pow(self.qty_decimals) as f64
+    }
+
+    /// Converts a decimal price to the nearest tick, `None` if it is not a finite number or
+    /// does not fit in ticks
+    pub fn to_price(&self, price: f64) -> Option<Price> {
+        Self::to_units(price, self.price_decimals, self.tick).map(Price)
+    }
+
+    /// Converts a decimal quantity to the nearest lot, `None` if it is not a finite number or
+    /// does not fit in lots
+    pub fn to_qty(&self, qty: f64) -> Option<Qty> {
+        Self::to_units(qty, self.qty_decimals, self.lot).map(Qty)
+    }
+
+    pub fn price_to_f64(&self, price: Price) -> f64 {
+        Self::from_units(price.0, self.price_decimals, self.tick)
+    }
+
+    pub fn qty_to_f64(&self, qty: Qty) -> f64 {
+        Self::from_units(qty.0, self.qty_decimals, self.lot)
+    }
+
+    /// Converts an amount in ticks times lots, such as a notional or a fee, to money
+    pub fn amount_to_f64(&self, amount: f64) -> f64 {
+        amount * self.tick_size() * self.lot_size()
+    }
+
+    /// Rounds a decimal price to the nearest valid tick
+    pub fn round_price(&self, price: f64) -> Option<f64> {
+        self.to_price(price).map(|price| self.price_to_f64(price))
+    }
+
+    /// Rounds a decimal quantity to the nearest valid lot
+    pub fn round_qty(&self, qty: f64) -> Option<f64> {
+        self.to_qty(qty).map(|qty| self.qty_to_f64(qty))
+    }
+
+    #[inline]
+    fn to_units(value: f64, decimals: u32, size: i64) -> Option<i64> {
+        let scaled = (value * 10_i64.pow(decimals) as f64).round();
+        // casts saturate, NaN and values out of range would silently become valid units
+        if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+            return None;
+        }
+        Some((scaled as i64 as f64 / size as f64).round() as i64)
+    }
+
+    #[inline]
+    fn from_units(units: i64, decimals: u32, size: i64) -> f64 {
+        (units * size) as f64 / 10_i64.pow(decimals) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_without_float_drift() {
+        let spec = InstrumentSpec::new(2, 5, 0, 1).unwrap();
+        assert_eq!(spec.to_price(100.05), Some(Price(2001)));
+        assert_eq!(spec.to_price(100.07), Some(Price(2001)));
+        assert_eq!(spec.price_to_f64(Price(2001)), 100.05);
+        assert_eq!(spec.to_qty(0.1 + 0.2 + 2.7), Some(Qty(3)));
+        assert_eq!(spec.tick_size(), 0.05);
+
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e30] {
+            assert_eq!(spec.to_qty(value), None);
+            assert_eq!(spec.to_price(value), None);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(InstrumentSpec::new(2, 0, 0, 1), None);
+        assert_eq!(InstrumentSpec::new(2, 1, 0, -1), None);
+        assert_eq!(
+            InstrumentSpec::from_decimals(InstrumentSpec::MAX_DECIMALS + 1, 0),
+            None
+        );
+        assert_eq!(InstrumentSpec::from_decimals(0, 19), None);
+
+        let spec = InstrumentSpec::from_decimals(
+            InstrumentSpec::MAX_DECIMALS,
+            InstrumentSpec::MAX_DECIMALS,
+        )
+        .unwrap();
+        assert_eq!(spec.price_to_f64(Price(1)), 1e-18);
+        assert_eq!(
+            InstrumentSpec::from_decimals(2, 0),
+            Some(InstrumentSpec::default())
+        );
+    }
+}
@@ -0,0 +1,566 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::matching_engine::market_data::SequenceGap;
+use crate::matching_engine::models::{OrderEvent, OrderType, Price};
+use crate::matching_engine::orderbook::OrderBook;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Inbound order with the sequence number and time the engine gave it
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Starts at 1 and grows by one with every order of the journal
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub order: OrderType,
+}
+
+/// Operation on a book other than an order. Commands share the sequence of the orders so a
+/// replay runs them at the same point of the order flow.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BookCommand {
+    BeginAuction,
+    EndAuction,
+    Uncross {
+        reference_price: Option<Price>,
+    },
+    /// Expires the good-till-date orders due at the time of the command
+    Expire,
+    ExpireDayOrders,
+    ResetTradeStats,
+}
+
+impl BookCommand {
+    /// Runs the command on `book` at `now`, returns the events it produced
+    pub fn run(self, book: &mut OrderBook, now: DateTime<Utc>) -> Vec<OrderEvent> {
+        match self {
+            BookCommand::BeginAuction => {
+                book.begin_auction();
+                Vec::new()
+            }
+            BookCommand::EndAuction => {
+                book.end_auction();
+                Vec::new()
+            }
+            BookCommand::Uncross { reference_price } => book.uncross(reference_price),
+            BookCommand::Expire => book.expire(now),
+            BookCommand::ExpireDayOrders => book.expire_day_orders(),
+            BookCommand::ResetTradeStats => {
+                book.reset_trade_stats();
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Command with the sequence number and time the engine gave it
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub command: BookCommand,
+}
+
+/// Record of a journal file. Every order or command is written before the book runs it, so
+/// the one that crashed an engine is in the journal, followed by the hash of the events once
+/// it ran.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JournalRecord {
+    Order(JournalEntry),
+    Command(CommandEntry),
+    /// Rolling hash of every event produced up to and including order or command `seq`
+    Output {
+        seq: u64,
+        hash: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    /// The file ends in the middle of a record
+    Truncated,
+    Gap(SequenceGap),
+    /// Replaying order or command `seq` produced other events than the recorded run
+    Diverged {
+        seq: u64,
+        expected: u64,
+        replayed: u64,
+    },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(error) => write!(f, "journal io error: {error}"),
+            JournalError::Encoding(error) => write!(f, "journal encoding error: {error}"),
+            JournalError::Truncated => write!(f, "journal ends in the middle of a record"),
+            JournalError::Gap(gap) => write!(f, "journal gap: {gap}"),
+            JournalError::Diverged {
+                seq,
+                expected,
+                replayed,
+            } => write!(
+                f,
+                "replay diverged at sequence number {seq}: hash {replayed:016x}, recorded {expected:016x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(error: io::Error) -> Self {
+        JournalError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for JournalError {
+    fn from(error: bincode::Error) -> Self {
+        JournalError::Encoding(error)
+    }
+}
+
+/// Folds events into a rolling FNV-1a hash of their binary encoding, stable across runs and
+/// platforms
+pub fn hash_events<'a>(
+    mut hash: u64,
+    events: impl IntoIterator<Item = &'a OrderEvent>,
+) -> Result<u64, JournalError> {
+    for event in events {
+        for byte in bincode::serialize(event)? {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    Ok(hash)
+}
+
+/// Append-only binary journal of the orders and commands sent to one book. Each record is a
+/// little endian `u32` length followed by the bincode encoding of a `JournalRecord`.
+///
+/// Entries carry no symbol, a journal records what reaches a single book and the caller
+/// keeps one per book. The order book simulator journals the orders it sends to the book
+/// of a listed symbol, auctions and expiries are journaled as the commands run on the book.
+#[derive(Debug)]
+pub struct Journal<W: Write> {
+    writer: W,
+    seq: u64,
+    hash: u64,
+}
+
+impl Journal<BufWriter<File>> {
+    /// Starts a new journal file, replacing any file at `path`
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Journal<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            seq: 0,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    /// Sequence number of the last order or command
+    #[inline(always)]
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Rolling hash of the events recorded so far
+    #[inline(always)]
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Records an order, the book must execute it at `timestamp`. Use `execute` when the
+    /// book is at hand.
+    pub fn append(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        order: OrderType,
+    ) -> Result<u64, JournalError> {
+        self.seq += 1;
+        self.write(&JournalRecord::Order(JournalEntry {
+            seq: self.seq,
+            timestamp,
+            order,
+        }))?;
+        Ok(self.seq)
+    }
+
+    /// Records a command, the book must run it at `timestamp`. Use `run` when the book is at
+    /// hand.
+    pub fn append_command(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        command: BookCommand,
+    ) -> Result<u64, JournalError> {
+        self.seq += 1;
+        self.write(&JournalRecord::Command(CommandEntry {
+            seq: self.seq,
+            timestamp,
+            command,
+        }))?;
+        Ok(self.seq)
+    }
+
+    /// Records the events the last appended order or command produced, cascade included
+    pub fn commit<'a>(
+        &mut self,
+        events: impl IntoIterator<Item = &'a OrderEvent>,
+    ) -> Result<u64, JournalError> {
+        self.hash = hash_events(self.hash, events)?;
+        self.write(&JournalRecord::Output {
+            seq: self.seq,
+            hash: self.hash,
+        })?;
+        Ok(self.hash)
+    }
+
+    /// Records an order and executes it on `book` at the time of the book clock
+    pub fn execute(
+        &mut self,
+        book: &mut OrderBook,
+        order: OrderType,
+    ) -> Result<OrderEvent, JournalError> {
+        let clock = book.clock();
+        let timestamp = clock.now();
+        self.append(timestamp, order)?;
+        book.set_time(timestamp);
+        let event = book.execute(order);
+        book.set_clock(clock);
+        self.commit(std::iter::once(&event).chain(book.cascade()))?;
+        Ok(event)
+    }
+
+    /// Records a command and runs it on `book` at the time of the book clock
+    pub fn run(
+        &mut self,
+        book: &mut OrderBook,
+        command: BookCommand,
+    ) -> Result<Vec<OrderEvent>, JournalError> {
+        let clock = book.clock();
+        let timestamp = clock.now();
+        self.append_command(timestamp, command)?;
+        book.set_time(timestamp);
+        let events = command.run(book, timestamp);
+        book.set_clock(clock);
+        self.commit(&events)?;
+        Ok(events)
+    }
+
+    pub fn flush(&mut self) -> Result<(), JournalError> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn write(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        let bytes = bincode::serialize(record)?;
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Reads the records of a journal in the order they were written
+#[derive(Debug)]
+pub struct JournalReader<R: Read> {
+    reader: R,
+}
+
+impl JournalReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> JournalReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn read_record(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        let mut len = [0u8; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.reader.read(&mut len[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(JournalError::Truncated),
+                n => read += n,
+            }
+        }
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader
+            .read_exact(&mut bytes)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => JournalError::Truncated,
+                _ => JournalError::Io(error),
+            })?;
+        Ok(Some(bincode::deserialize(&bytes)?))
+    }
+}
+
+impl<R: Read> Iterator for JournalReader<R> {
+    type Item = Result<JournalRecord, JournalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Book rebuilt from a journal, with every event it produced on the way
+#[derive(Debug)]
+pub struct Replay {
+    pub book: OrderBook,
+    pub events: Vec<OrderEvent>,
+    /// Number of orders replayed
+    pub orders: u64,
+    /// Number of commands replayed
+    pub commands: u64,
+    /// Sequence number of the last order or command
+    pub seq: u64,
+    pub hash: u64,
+}
+
+/// Executes the orders and commands of a journal on `book`, at their recorded times, and
+/// checks the events match the recorded run after each of them. `book` must be configured
+/// like the recorded one and hold no orders.
+pub fn replay(
+    records: impl IntoIterator<Item = Result<JournalRecord, JournalError>>,
+    book: OrderBook,
+) -> Result<Replay, JournalError> {
+    resume(records, book, 0, FNV_OFFSET)
+}
+
+/// Replays the orders and commands of a journal after `seq` on a book restored from a
+/// snapshot taken once `seq` ran, `hash` being the hash of the journal at that point
+pub fn resume(
+    records: impl IntoIterator<Item = Result<JournalRecord, JournalError>>,
+    mut book: OrderBook,
+    seq: u64,
+    mut hash: u64,
+) -> Result<Replay, JournalError> {
+    let clock = book.clock();
+    let mut events = Vec::new();
+    let (mut orders, mut commands) = (0, 0);
+    let mut last_seq = seq;
+    for record in records {
+        match record? {
+            JournalRecord::Order(entry) if entry.seq <= seq => {}
+            JournalRecord::Command(entry) if entry.seq <= seq => {}
+            JournalRecord::Output { seq: output, .. } if output <= seq => {}
+            JournalRecord::Order(entry) => {
+                advance(&mut last_seq, entry.seq)?;
+                orders += 1;
+                book.set_time(entry.timestamp);
+                let first = events.len();
+                events.push(book.execute(entry.order));
+                events.extend_from_slice(book.cascade());
+                hash = hash_events(hash, &events[first..])?;
+            }
+            JournalRecord::Command(entry) => {
+                advance(&mut last_seq, entry.seq)?;
+                commands += 1;
+                book.set_time(entry.timestamp);
+                let first = events.len();
+                events.extend(entry.command.run(&mut book, entry.timestamp));
+                hash = hash_events(hash, &events[first..])?;
+            }
+            JournalRecord::Output {
+                seq,
+                hash: expected,
+            } => {
+                if seq != last_seq {
+                    return Err(JournalError::Gap(SequenceGap {
+                        expected: last_seq,
+                        received: seq,
+                    }));
+                }
+                if hash != expected {
+                    return Err(JournalError::Diverged {
+                        seq,
+                        expected,
+                        replayed: hash,
+                    });
+                }
+            }
+        }
+    }
+    book.set_clock(clock);
+    Ok(Replay {
+        book,
+        events,
+        orders,
+        commands,
+        seq: last_seq,
+        hash,
+    })
+}
+
+/// Moves to the sequence number of the next order or command, which has to follow the last
+fn advance(last_seq: &mut u64, seq: u64) -> Result<(), JournalError> {
+    if seq != *last_seq + 1 {
+        return Err(JournalError::Gap(SequenceGap {
+            expected: *last_seq + 1,
+            received: seq,
+        }));
+    }
+    *last_seq = seq;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::clock::Clock;
+    use crate::matching_engine::models::{Price, Qty, Side, StpMode, TimeInForce};
+    use crate::matching_engine::snapshot::BookSnapshot;
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use chrono::{Duration, TimeZone};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use uuid::Uuid;
+
+    fn book() -> OrderBook {
+        let mut book = OrderBook::new(64);
+        book.set_stp_mode(StpMode::CancelNewest);
+        book
+    }
+
+    fn record(orders: usize) -> (Vec<u8>, Vec<OrderEvent>, OrderBook) {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut book = book();
+        let start = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+        let mut journal = Journal::new(Vec::new());
+        let mut events = Vec::new();
+        let mut ids = Vec::new();
+        for i in 0..orders {
+            book.set_clock(Clock::Manual(start + Duration::milliseconds(i as i64)));
+            let side = if rng.gen_bool(0.5) {
+                Side::Bid
+            } else {
+                Side::Ask
+            };
+            let order = match rng.gen_range(0..10) {
+                0 if !ids.is_empty() => OrderType::Cancel {
+                    id: ids[rng.gen_range(0..ids.len())],
+                },
+                1 => OrderType::Stop {
+                    id: Uuid::new_v4(),
+                    owner: rng.gen_range(0..4),
+                    side,
+                    qty: Qty(rng.gen_range(1..10)),
+                    stop_price: Price(rng.gen_range(95..105)),
+                },
+                _ => {
+                    let id = Uuid::new_v4();
+                    ids.push(id);
+                    let owner = rng.gen_range(0..4);
+                    limit(id, side, rng.gen_range(1..20), rng.gen_range(95..105)).owner(owner)
+                }
+            };
+            events.push(journal.execute(&mut book, order).unwrap());
+            events.extend_from_slice(book.cascade());
+        }
+        (journal.writer, events, book)
+    }
+
+    #[test]
+    fn replay_reproduces_the_run() {
+        let (bytes, events, book) = record(500);
+        let replayed = replay(JournalReader::new(bytes.as_slice()), self::book()).unwrap();
+        assert_eq!(replayed.orders, 500);
+        assert_eq!(replayed.events, events);
+        let (snapshot, recorded) = (replayed.book.l3_snapshot(), book.l3_snapshot());
+        assert_eq!(
+            (snapshot.asks, snapshot.bids),
+            (recorded.asks, recorded.bids)
+        );
+        assert_eq!(replayed.book.trade_stats(), book.trade_stats());
+    }
+
+    #[test]
+    fn resume_from_a_snapshot() {
+        let (bytes, events, _) = record(300);
+        let records = || JournalReader::new(bytes.as_slice());
+        let checkpoint = replay(records().take(2 * 120), book()).unwrap();
+        assert_eq!(checkpoint.seq, 120);
+        let snapshot = BookSnapshot::from_bytes(&checkpoint.book.snapshot().to_bytes().unwrap());
+
+        let mut restored = OrderBook::new(64);
+        restored.restore(snapshot.unwrap());
+        let resumed = resume(records(), restored, checkpoint.seq, checkpoint.hash).unwrap();
+        assert_eq!(resumed.orders, 180);
+        assert_eq!(resumed.hash, replay(records(), book()).unwrap().hash);
+        assert_eq!([checkpoint.events, resumed.events].concat(), events);
+    }
+
+    #[test]
+    fn replay_runs_auctions_and_expiries() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+        let mut book = book();
+        book.set_time(start);
+        let mut journal = Journal::new(Vec::new());
+        let mut events = journal.run(&mut book, BookCommand::BeginAuction).unwrap();
+        for order in [
+            limit(Uuid::new_v4(), Side::Ask, 5, 100).owner(1),
+            limit(Uuid::new_v4(), Side::Bid, 3, 101)
+                .owner(1)
+                .tif(TimeInForce::Day),
+            limit(Uuid::new_v4(), Side::Bid, 4, 99)
+                .owner(1)
+                .tif(TimeInForce::Day),
+            limit(Uuid::new_v4(), Side::Ask, 2, 105)
+                .owner(1)
+                .tif(TimeInForce::GoodTillDate(start)),
+        ] {
+            events.push(journal.execute(&mut book, order).unwrap());
+        }
+        for command in [
+            BookCommand::Uncross {
+                reference_price: None,
+            },
+            BookCommand::Expire,
+            BookCommand::ExpireDayOrders,
+        ] {
+            events.extend(journal.run(&mut book, command).unwrap());
+        }
+        assert_eq!(book.open_orders(), 1);
+
+        let replayed = replay(JournalReader::new(journal.writer.as_slice()), self::book()).unwrap();
+        assert_eq!((replayed.orders, replayed.commands), (4, 4));
+        assert_eq!(replayed.events, events);
+        assert_eq!(replayed.hash, journal.hash);
+        assert_eq!(replayed.book.open_orders(), 1);
+    }
+
+    #[test]
+    fn replay_detects_divergence_and_truncation() {
+        let (bytes, _, _) = record(50);
+        // a book configured differently produces other events
+        let mut other = book();
+        other.set_stp_mode(StpMode::CancelOldest);
+        assert!(matches!(
+            replay(JournalReader::new(bytes.as_slice()), other),
+            Err(JournalError::Diverged { .. })
+        ));
+
+        let cut = &bytes[..bytes.len() - 3];
+        assert!(matches!(
+            replay(JournalReader::new(cut), book()),
+            Err(JournalError::Truncated)
+        ));
+    }
+}
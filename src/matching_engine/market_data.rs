@@ -0,0 +1,413 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::matching_engine::models::{BookLevel, Price, Qty, Side};
+use crate::matching_engine::orderbook::OrderBook;
+
+/// Change to the public view of a book. Quantities are displayed quantities, the reserve of
+/// iceberg orders is never published.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketData {
+    /// First order at a price
+    LevelNew {
+        side: Side,
+        price: Price,
+        qty: Qty,
+        orders: usize,
+    },
+    LevelChange {
+        side: Side,
+        price: Price,
+        qty: Qty,
+        orders: usize,
+    },
+    /// Last order at a price is gone
+    LevelDelete {
+        side: Side,
+        price: Price,
+    },
+    /// Order appended to the back of its level
+    OrderAdd {
+        id: Uuid,
+        side: Side,
+        price: Price,
+        qty: Qty,
+    },
+    /// New displayed quantity of an order that keeps its place in the queue
+    OrderModify {
+        id: Uuid,
+        side: Side,
+        price: Price,
+        qty: Qty,
+    },
+    OrderDelete {
+        id: Uuid,
+        side: Side,
+        price: Price,
+    },
+    Trade {
+        /// Id of the trade on the tape of the book
+        id: u64,
+        price: Price,
+        qty: Qty,
+        aggressor: Side,
+        taker: Uuid,
+        maker: Uuid,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketDataMessage {
+    /// Starts at 1 and grows by one with every message of the book
+    pub seq: u64,
+    pub data: MarketData,
+}
+
+/// Collects the market data of a book while it changes. Order messages are published as they
+/// happen, level messages are coalesced and published once per book call by `flush_levels`.
+#[derive(Debug, Default)]
+pub struct MarketDataFeed {
+    enabled: bool,
+    seq: u64,
+    messages: Vec<MarketDataMessage>,
+    /// Levels changed since the last flush, with their quantity and order count before the
+    /// first change
+    touched: IndexMap<(Side, Price), Option<(Qty, usize)>>,
+}
+
+impl MarketDataFeed {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sequence number of the last message
+    #[inline(always)]
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Drops pending messages and continues numbering after `seq`
+    pub fn reset(&mut self, enabled: bool, seq: u64) {
+        self.enabled = enabled;
+        self.seq = seq;
+        self.messages.clear();
+        self.touched.clear();
+    }
+
+    pub fn publish(&mut self, data: MarketData) {
+        if !self.enabled {
+            return;
+        }
+        self.seq += 1;
+        self.messages.push(MarketDataMessage {
+            seq: self.seq,
+            data,
+        });
+    }
+
+    /// Records the state of a level before it changes
+    pub fn touch(&mut self, side: Side, price: Price, before: Option<(Qty, usize)>) {
+        if self.enabled {
+            self.touched.entry((side, price)).or_insert(before);
+        }
+    }
+
+    /// Publishes one message for every touched level whose state differs from before its
+    /// first change. `level` returns the current quantity and order count of a level.
+    pub fn flush_levels(&mut self, level: impl Fn(Side, Price) -> Option<(Qty, usize)>) {
+        let touched = std::mem::take(&mut self.touched);
+        for ((side, price), before) in touched {
+            let data = match (before, level(side, price)) {
+                (None, Some((qty, orders))) => MarketData::LevelNew {
+                    side,
+                    price,
+                    qty,
+                    orders,
+                },
+                (Some(before), Some((qty, orders))) if before != (qty, orders) => {
+                    MarketData::LevelChange {
+                        side,
+                        price,
+                        qty,
+                        orders,
+                    }
+                }
+                (Some(_), None) => MarketData::LevelDelete { side, price },
+                _ => continue,
+            };
+            self.publish(data);
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<MarketDataMessage> {
+        std::mem::take(&mut self.messages)
+    }
+}
+
+/// A message arrived out of order
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub expected: u64,
+    pub received: u64,
+}
+
+impl fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected message {}, received {}",
+            self.expected, self.received
+        )
+    }
+}
+
+impl std::error::Error for SequenceGap {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct MirrorOrder {
+    id: Uuid,
+    qty: Qty,
+}
+
+/// Reference consumer of the market data feed, mirrors a book from its messages
+#[derive(Debug, Default)]
+pub struct BookBuilder {
+    last_seq: u64,
+    asks: BTreeMap<Price, BookLevel>,
+    bids: BTreeMap<Price, BookLevel>,
+    orders: HashMap<(Side, Price), VecDeque<MirrorOrder>>,
+    traded_volume: Qty,
+    trades: u64,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, message: &MarketDataMessage) -> Result<(), SequenceGap> {
+        if message.seq != self.last_seq + 1 {
+            return Err(SequenceGap {
+                expected: self.last_seq + 1,
+                received: message.seq,
+            });
+        }
+        self.last_seq = message.seq;
+        match message.data {
+            MarketData::LevelNew {
+                side,
+                price,
+                qty,
+                orders,
+            }
+            | MarketData::LevelChange {
+                side,
+                price,
+                qty,
+                orders,
+            } => {
+                self.levels_mut(side)
+                    .insert(price, BookLevel { price, qty, orders });
+            }
+            MarketData::LevelDelete { side, price } => {
+                self.levels_mut(side).remove(&price);
+            }
+            MarketData::OrderAdd {
+                id,
+                side,
+                price,
+                qty,
+            } => self
+                .orders
+                .entry((side, price))
+                .or_default()
+                .push_back(MirrorOrder { id, qty }),
+            MarketData::OrderModify {
+                id,
+                side,
+                price,
+                qty,
+            } => {
+                if let Some(order) = self
+                    .orders
+                    .get_mut(&(side, price))
+                    .and_then(|queue| queue.iter_mut().find(|order| order.id == id))
+                {
+                    order.qty = qty;
+                }
+            }
+            MarketData::OrderDelete { id, side, price } => {
+                if let Some(queue) = self.orders.get_mut(&(side, price)) {
+                    queue.retain(|order| order.id != id);
+                    if queue.is_empty() {
+                        self.orders.remove(&(side, price));
+                    }
+                }
+            }
+            MarketData::Trade { qty, .. } => {
+                self.traded_volume += qty;
+                self.trades += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Levels of a side from the best price
+    pub fn levels(&self, side: Side) -> Vec<BookLevel> {
+        match side {
+            Side::Ask => self.asks.values().copied().collect(),
+            Side::Bid => self.bids.values().rev().copied().collect(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn traded_volume(&self) -> Qty {
+        self.traded_volume
+    }
+
+    #[inline(always)]
+    pub fn trades(&self) -> u64 {
+        self.trades
+    }
+
+    /// Whether the mirrored levels and orders are the same as the displayed levels and orders
+    /// of `book`, in the same priority
+    pub fn matches(&self, book: &OrderBook) -> bool {
+        let depth = book.depth(usize::MAX);
+        [(Side::Ask, depth.asks), (Side::Bid, depth.bids)]
+            .into_iter()
+            .all(|(side, book_levels)| {
+                let levels = self.levels(side);
+                let orders: Vec<(Uuid, Price, Qty)> = levels
+                    .iter()
+                    .flat_map(|level| {
+                        self.orders
+                            .get(&(side, level.price))
+                            .into_iter()
+                            .flatten()
+                            .map(move |order| (order.id, level.price, order.qty))
+                    })
+                    .collect();
+                let book_orders: Vec<(Uuid, Price, Qty)> = book
+                    .orders(side)
+                    .map(|order| (order.id, order.price, order.qty))
+                    .collect();
+                levels == book_levels && orders == book_orders
+            })
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<Price, BookLevel> {
+        match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::{OrderType, StpMode};
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn builder_mirrors_the_book() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut book = OrderBook::new(256);
+        book.set_market_data(true);
+        book.set_stp_mode(StpMode::DecrementAndCancel);
+        let mut builder = BookBuilder::new();
+        let mut ids = Vec::new();
+
+        for i in 0..2_000 {
+            // runs a short call auction now and then
+            match i % 400 {
+                0 => book.begin_auction(),
+                50 => {
+                    book.uncross(None);
+                }
+                _ => {}
+            }
+            let side = if rng.gen_bool(0.5) {
+                Side::Bid
+            } else {
+                Side::Ask
+            };
+            let order = match rng.gen_range(0..10) {
+                6 => OrderType::Market {
+                    id: Uuid::new_v4(),
+                    owner: rng.gen_range(0..4),
+                    side,
+                    qty: Qty(rng.gen_range(1..30)),
+                    protection: None,
+                    min_qty: None,
+                },
+                7 | 8 if !ids.is_empty() => OrderType::Cancel {
+                    id: ids[rng.gen_range(0..ids.len())],
+                },
+                9 if !ids.is_empty() => OrderType::Modify {
+                    id: ids[rng.gen_range(0..ids.len())],
+                    new_price: Price(rng.gen_range(95..105)),
+                    new_qty: Qty(rng.gen_range(1..20)),
+                },
+                _ => {
+                    let id = Uuid::new_v4();
+                    ids.push(id);
+                    let owner = rng.gen_range(0..4);
+                    let order =
+                        limit(id, side, rng.gen_range(1..20), rng.gen_range(95..105)).owner(owner);
+                    if rng.gen_bool(0.2) {
+                        order.display_qty(rng.gen_range(1..5))
+                    } else {
+                        order
+                    }
+                }
+            };
+            book.execute(order);
+            for message in book.drain_market_data() {
+                builder.apply(&message).unwrap();
+            }
+            assert!(builder.matches(&book));
+        }
+        assert_eq!(builder.traded_volume(), book.traded_volume());
+    }
+
+    #[test]
+    fn builder_detects_gaps() {
+        let mut book = OrderBook::new(16);
+        book.set_market_data(true);
+        for price in [100, 101] {
+            book.execute(limit(Uuid::new_v4(), Side::Ask, 1, price));
+        }
+        let messages = book.drain_market_data();
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(
+            messages[1].data,
+            MarketData::LevelNew {
+                side: Side::Ask,
+                orders: 1,
+                ..
+            }
+        ));
+
+        let mut builder = BookBuilder::new();
+        builder.apply(&messages[0]).unwrap();
+        assert_eq!(
+            builder.apply(&messages[2]),
+            Err(SequenceGap {
+                expected: 2,
+                received: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+use crate::matching_engine::models::Qty;
+
+/// Decides how an incoming order is split between the resting orders of a price level
+pub trait MatchingPolicy: Debug + Send + Sync {
+    /// `resting` yields the displayed quantity of each order of the level in time priority.
+    /// Returns the quantity allocated to each order, in the same order, for as many orders as
+    /// were taken from `resting`. Allocations add up to `qty`, or to the whole level when it
+    /// holds less than `qty`.
+    fn allocate(&self, qty: Qty, resting: &mut dyn Iterator<Item = Qty>) -> Vec<Qty>;
+}
+
+/// Price-time priority, the oldest order at a price is filled first
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Fifo;
+
+impl MatchingPolicy for Fifo {
+    fn allocate(&self, qty: Qty, resting: &mut dyn Iterator<Item = Qty>) -> Vec<Qty> {
+        let mut allocations = Vec::new();
+        let mut left = qty;
+        for available in resting {
+            if left.is_zero() {
+                break;
+            }
+            let allocation = left.min(available);
+            left -= allocation;
+            allocations.push(allocation);
+        }
+        allocations
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProRataRounding {
+    /// Rounds every share down
+    #[default]
+    Down,
+    /// Rounds every share to the nearest lot, the newest orders give back any excess
+    Nearest,
+}
+
+/// Splits the incoming quantity in proportion to the size of each resting order.
+/// Shares below `min_allocation` are dropped, and whatever is left after rounding goes to the
+/// orders in time priority.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ProRata {
+    pub min_allocation: Qty,
+    pub rounding: ProRataRounding,
+}
+
+impl ProRata {
+    pub fn new(min_allocation: Qty, rounding: ProRataRounding) -> Self {
+        Self {
+            min_allocation,
+            rounding,
+        }
+    }
+
+    fn allocate_level(&self, qty: Qty, resting: &[Qty]) -> Vec<Qty> {
+        let total = resting.iter().fold(Qty::ZERO, |acc, q| acc + *q);
+        if total <= qty {
+            return resting.to_vec();
+        }
+
+        let mut allocations: Vec<Qty> = resting
+            .iter()
+            .map(|available| {
+                let share = qty.0 as i128 * available.0 as i128;
+                let allocation = match self.rounding {
+                    ProRataRounding::Down => share / total.0 as i128,
+                    ProRataRounding::Nearest => (share + total.0 as i128 / 2) / total.0 as i128,
+                };
+                let allocation = Qty(allocation as i64).min(*available);
+                if allocation < self.min_allocation {
+                    Qty::ZERO
+                } else {
+                    allocation
+                }
+            })
+            .collect();
+
+        let mut allocated = allocations.iter().fold(Qty::ZERO, |acc, q| acc + *q);
+        for allocation in allocations.iter_mut().rev() {
+            if allocated <= qty {
+                break;
+            }
+            let excess = (allocated - qty).min(*allocation);
+            *allocation -= excess;
+            allocated -= excess;
+        }
+        for (allocation, available) in allocations.iter_mut().zip(resting) {
+            if allocated >= qty {
+                break;
+            }
+            let extra = (qty - allocated).min(*available - *allocation);
+            *allocation += extra;
+            allocated += extra;
+        }
+        allocations
+    }
+}
+
+impl MatchingPolicy for ProRata {
+    fn allocate(&self, qty: Qty, resting: &mut dyn Iterator<Item = Qty>) -> Vec<Qty> {
+        let resting: Vec<Qty> = resting.collect();
+        self.allocate_level(qty, &resting)
+    }
+}
+
+/// The order at the front of the queue is filled first, up to `top_order_cap` when set, and the
+/// rest is split pro-rata between every order of the level
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FifoProRata {
+    pub top_order_cap: Option<Qty>,
+    pub pro_rata: ProRata,
+}
+
+impl FifoProRata {
+    pub fn new(top_order_cap: Option<Qty>, pro_rata: ProRata) -> Self {
+        Self {
+            top_order_cap,
+            pro_rata,
+        }
+    }
+}
+
+impl MatchingPolicy for FifoProRata {
+    fn allocate(&self, qty: Qty, resting: &mut dyn Iterator<Item = Qty>) -> Vec<Qty> {
+        let mut resting: Vec<Qty> = resting.collect();
+        let top = match resting.first() {
+            Some(top) => *top,
+            None => return Vec::new(),
+        };
+        let top_allocation = match self.top_order_cap {
+            Some(cap) => qty.min(top).min(cap),
+            None => qty.min(top),
+        };
+        resting[0] -= top_allocation;
+
+        let mut allocations = self.pro_rata.allocate_level(qty - top_allocation, &resting);
+        allocations[0] += top_allocation;
+        allocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocate(policy: &dyn MatchingPolicy, qty: i64, resting: &[i64]) -> Vec<i64> {
+        policy
+            .allocate(Qty(qty), &mut resting.iter().map(|q| Qty(*q)))
+            .into_iter()
+            .map(|q| q.0)
+            .collect()
+    }
+
+    #[test]
+    fn fifo_fills_oldest_first() {
+        assert_eq!(allocate(&Fifo, 7, &[5, 5, 5]), vec![5, 2]);
+    }
+
+    #[test]
+    fn pro_rata_applies_minimum_and_rounding() {
+        let policy = ProRata::new(Qty(2), ProRataRounding::Down);
+        // shares are 5, 3.33 and 1.66, the last one is under the minimum and the two lots left
+        // after rounding go to the oldest order
+        assert_eq!(allocate(&policy, 10, &[30, 20, 10]), vec![7, 3, 0]);
+        assert_eq!(allocate(&policy, 10, &[3, 2]), vec![3, 2]);
+
+        let policy = ProRata::new(Qty::ZERO, ProRataRounding::Nearest);
+        assert_eq!(allocate(&policy, 3, &[5, 5]), vec![2, 1]);
+    }
+
+    #[test]
+    fn top_order_gets_priority() {
+        let policy = FifoProRata::new(Some(Qty(4)), ProRata::default());
+        assert_eq!(allocate(&policy, 10, &[8, 6, 6]), vec![6, 2, 2]);
+    }
+}
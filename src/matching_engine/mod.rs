@@ -1,3 +1,19 @@
+pub mod accounts;
 pub mod arena;
+pub mod auction;
+pub mod bars;
+pub mod clock;
+pub mod exchange;
+pub mod fees;
+pub mod instrument;
+pub mod journal;
+pub mod market_data;
+pub mod matching;
 pub mod models;
 pub mod orderbook;
+pub mod session;
+pub mod snapshot;
+pub mod tape;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod triggers;
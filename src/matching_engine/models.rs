@@ -1,7 +1,79 @@
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
 use uuid::Uuid;
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+/// Price expressed as an integer number of ticks
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Price(pub i64);
+
+/// Quantity expressed as an integer number of lots
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Qty(pub i64);
+
+macro_rules! impl_units {
+    ($name:ident) => {
+        impl $name {
+            pub const ZERO: $name = $name(0);
+            /// Largest value an order may carry, so that sums over many orders and products
+            /// of prices and quantities in `i128` cannot overflow
+            pub const MAX: $name = $name(1 << 40);
+
+            #[inline(always)]
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+
+            #[inline(always)]
+            fn add(self, rhs: $name) -> $name {
+                $name(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+
+            #[inline(always)]
+            fn sub(self, rhs: $name) -> $name {
+                $name(self.0 - rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            #[inline(always)]
+            fn add_assign(&mut self, rhs: $name) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            #[inline(always)]
+            fn sub_assign(&mut self, rhs: $name) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+impl_units!(Price);
+impl_units!(Qty);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Side {
     Bid,
     Ask,
@@ -18,22 +90,127 @@ impl std::ops::Not for Side {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// How long a limit order stays active
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Rests until it is filled or canceled
+    #[default]
+    GoodTillCancel,
+    /// Fills what it can immediately and cancels the remainder
+    ImmediateOrCancel,
+    /// Fills completely and immediately or not at all
+    FillOrKill,
+    /// Rests until it is filled, canceled or the expiry time passes
+    GoodTillDate(DateTime<Utc>),
+    /// Rests until it is filled, canceled or the session ends
+    Day,
+}
+
+impl TimeInForce {
+    /// Whether the unfilled quantity rests in the book
+    #[inline(always)]
+    pub fn is_resting(self) -> bool {
+        !matches!(
+            self,
+            TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill
+        )
+    }
+}
+
+/// Distance from the opposite touch a market order may trade through
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriceBand {
+    Ticks(i64),
+    /// Hundredths of a percent of the touch price, rounded down to whole ticks
+    BasisPoints(i64),
+}
+
+impl PriceBand {
+    /// Worst price an order on `side` may trade at when the opposite touch is at `touch`
+    pub fn limit(self, side: Side, touch: Price) -> Price {
+        let touch = touch.0 as i128;
+        let width = match self {
+            PriceBand::Ticks(ticks) => ticks as i128,
+            PriceBand::BasisPoints(bps) => touch * bps as i128 / 10_000,
+        };
+        let limit = match side {
+            Side::Bid => touch + width,
+            Side::Ask => touch - width,
+        };
+        Price(limit.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OrderType {
     Market {
         id: Uuid,
+        owner: u64,
         side: Side,
-        qty: f64,
+        qty: Qty,
+        /// Band from the touch beyond which the order is canceled, the book default if `None`
+        protection: Option<PriceBand>,
+        /// Least quantity that must fill on arrival, otherwise nothing trades
+        min_qty: Option<Qty>,
     },
     Limit {
         id: Uuid,
+        owner: u64,
+        side: Side,
+        qty: Qty,
+        price: Price,
+        tif: TimeInForce,
+        /// Only adds liquidity, see `PostOnlyMode` for orders that would cross
+        post_only: bool,
+        /// Shown quantity of an iceberg order, the rest of `qty` is kept as a hidden reserve
+        display_qty: Option<Qty>,
+        /// Least quantity that must fill on arrival, otherwise nothing trades
+        min_qty: Option<Qty>,
+        /// Only trades its whole quantity at once, incoming orders too small to fill it pass
+        /// it by. It can leave the book crossed while it waits, and call auctions leave it out
+        /// of the uncross unless it fills completely.
+        all_or_none: bool,
+    },
+    /// Market order sent once the last trade price reaches `stop_price`
+    Stop {
+        id: Uuid,
+        owner: u64,
         side: Side,
-        qty: f64,
-        price: f64,
+        qty: Qty,
+        stop_price: Price,
+    },
+    /// Limit order sent once the last trade price reaches `stop_price`
+    StopLimit {
+        id: Uuid,
+        owner: u64,
+        side: Side,
+        qty: Qty,
+        stop_price: Price,
+        price: Price,
+        tif: TimeInForce,
     },
     Cancel {
         id: Uuid,
     },
+    /// Amends a resting limit order. `new_qty` is the new open quantity of the order.
+    Modify {
+        id: Uuid,
+        new_price: Price,
+        new_qty: Qty,
+    },
+}
+
+impl OrderType {
+    pub fn id(&self) -> Uuid {
+        match self {
+            OrderType::Market { id, .. }
+            | OrderType::Limit { id, .. }
+            | OrderType::Stop { id, .. }
+            | OrderType::StopLimit { id, .. }
+            | OrderType::Cancel { id }
+            | OrderType::Modify { id, .. } => *id,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
@@ -49,39 +226,290 @@ pub enum OrderEvent {
     },
     PartiallyFilled {
         id: Uuid,
-        filled_qty: f64,
+        filled_qty: Qty,
         fills: Vec<FillMetadata>,
     },
     Filled {
         id: Uuid,
-        filled_qty: f64,
+        filled_qty: Qty,
+        fills: Vec<FillMetadata>,
+    },
+    Modified {
+        id: Uuid,
+        price: Price,
+        qty: Qty,
+        fills: Vec<FillMetadata>,
+    },
+    /// Market and immediate-or-cancel orders whose unfilled quantity was canceled
+    PartiallyFilledCanceled {
+        id: Uuid,
+        filled_qty: Qty,
+        canceled_qty: Qty,
         fills: Vec<FillMetadata>,
     },
+    /// Market order that reached the edge of its protection band, the rest was canceled
+    Protected {
+        id: Uuid,
+        filled_qty: Qty,
+        canceled_qty: Qty,
+        /// Worst price the order was allowed to trade at
+        limit_price: Price,
+        fills: Vec<FillMetadata>,
+    },
+    /// Fill-or-kill order that could not be filled completely, or order that could not fill
+    /// its minimum quantity
+    Killed {
+        id: Uuid,
+    },
+    /// Good-till-date or day order removed by an expiry sweep
+    Expired {
+        id: Uuid,
+        qty: Qty,
+    },
+    /// Post-only order moved one tick behind the touch and placed
+    Repriced {
+        id: Uuid,
+        price: Price,
+    },
+    Rejected {
+        id: Uuid,
+        reason: RejectReason,
+    },
+    /// Stop order whose stop price was reached, followed by the event of its execution
+    Triggered {
+        id: Uuid,
+    },
+    /// Quantity of an order canceled by self-trade prevention, see `StpMode`
+    SelfTradePrevented {
+        id: Uuid,
+        canceled_qty: Qty,
+    },
 }
 
+impl OrderEvent {
+    /// Id of the order the event is about
+    pub fn id(&self) -> Uuid {
+        match self {
+            OrderEvent::Unfilled { id }
+            | OrderEvent::Placed { id }
+            | OrderEvent::Canceled { id }
+            | OrderEvent::PartiallyFilled { id, .. }
+            | OrderEvent::Filled { id, .. }
+            | OrderEvent::Modified { id, .. }
+            | OrderEvent::PartiallyFilledCanceled { id, .. }
+            | OrderEvent::Protected { id, .. }
+            | OrderEvent::Killed { id }
+            | OrderEvent::Expired { id, .. }
+            | OrderEvent::Repriced { id, .. }
+            | OrderEvent::Rejected { id, .. }
+            | OrderEvent::Triggered { id }
+            | OrderEvent::SelfTradePrevented { id, .. } => *id,
+        }
+    }
+
+    pub fn fills(&self) -> &[FillMetadata] {
+        match self {
+            OrderEvent::PartiallyFilled { fills, .. }
+            | OrderEvent::Filled { fills, .. }
+            | OrderEvent::Modified { fills, .. }
+            | OrderEvent::PartiallyFilledCanceled { fills, .. }
+            | OrderEvent::Protected { fills, .. } => fills,
+            _ => &[],
+        }
+    }
+
+    pub fn fills_mut(&mut self) -> &mut [FillMetadata] {
+        match self {
+            OrderEvent::PartiallyFilled { fills, .. }
+            | OrderEvent::Filled { fills, .. }
+            | OrderEvent::Modified { fills, .. }
+            | OrderEvent::PartiallyFilledCanceled { fills, .. }
+            | OrderEvent::Protected { fills, .. } => fills,
+            _ => &mut [],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// Post-only order that would take liquidity
+    WouldCross,
+    /// Market, immediate-or-cancel and fill-or-kill orders cannot rest during an auction
+    NotAllowedInAuction,
+    /// Only cancels are accepted while trading is halted
+    TradingHalted,
+    /// Only cancels are accepted while the market is closed
+    MarketClosed,
+    /// The symbol is not listed on the exchange
+    UnknownInstrument,
+    /// Quantity is zero, negative or above `Qty::MAX`
+    InvalidQty,
+    /// Limit or stop price is zero, negative or above `Price::MAX`
+    InvalidPrice,
+    /// Displayed quantity of an iceberg order is zero or negative, or set on an all-or-none
+    /// order
+    InvalidDisplayQty,
+    /// Minimum quantity is zero, negative or above the order quantity
+    InvalidMinQty,
+    /// Another open order already uses the id
+    DuplicateOrderId,
+    /// Cancel or modify of an order that is not open
+    UnknownOrder,
+    /// The book already holds as many resting orders as its capacity allows
+    ArenaFull,
+    /// Protection band of a market order is zero or negative
+    InvalidProtection,
+}
+
+/// What happens when an incoming order would trade with a resting order of the same owner
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StpMode {
+    /// Orders of the same owner trade with each other
+    #[default]
+    Disabled,
+    /// Cancels the incoming order
+    CancelNewest,
+    /// Cancels the resting order and keeps matching the incoming one
+    CancelOldest,
+    /// Cancels both orders
+    CancelBoth,
+    /// Decrements both orders by the smaller quantity and cancels the one left empty
+    DecrementAndCancel,
+}
+
+/// What happens to a post-only order that would cross the book
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostOnlyMode {
+    #[default]
+    Reject,
+    /// Moves the order one tick behind the opposite touch
+    Reprice,
+}
+
+/// One match between two orders, `_1` fields are for the taker and `_2` fields for the maker
 #[derive(Debug, PartialEq, Copy, Clone, Serialize)]
 pub struct FillMetadata {
     pub order_1: Uuid,
     pub order_2: Uuid,
-    pub qty: f64,
-    pub price: f64,
+    pub owner_1: u64,
+    pub owner_2: u64,
+    pub qty: Qty,
+    pub price: Price,
     pub taker_side: Side,
     pub total_fill: bool,
+    /// Fees charged to each side in ticks times lots, negative for a rebate
+    pub fee_1: f64,
+    pub fee_2: f64,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// One fill as printed on the trade tape
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Trade {
-    pub total_qty: f64,
-    pub avg_price: f64,
-    pub last_price: f64,
-    pub last_qty: f64,
+    /// Starts at 1 and grows by one with every trade of the book
+    pub id: u64,
+    pub price: Price,
+    pub qty: Qty,
+    /// Side of the taker
+    pub aggressor: Side,
+    pub taker: Uuid,
+    pub maker: Uuid,
+    pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LimitOrder {
     pub id: Uuid,
-    pub qty: f64,
-    pub price: f64,
+    pub owner: u64,
+    pub side: Side,
+    /// Displayed quantity
+    pub qty: Qty,
+    pub price: Price,
+    pub tif: TimeInForce,
+    /// Reserve of an iceberg order, not shown in the book
+    pub hidden_qty: Qty,
+    /// Quantity shown on each refresh of an iceberg order
+    pub display_qty: Option<Qty>,
+    /// Time the order entered its queue, set when it rests
+    pub timestamp: DateTime<Utc>,
+    /// Only matched in full, see `OrderType::Limit`
+    pub all_or_none: bool,
+    /// Only adds liquidity, also when it is modified
+    pub post_only: bool,
+}
+
+impl LimitOrder {
+    pub fn new(
+        id: Uuid,
+        owner: u64,
+        side: Side,
+        price: Price,
+        qty: Qty,
+        tif: TimeInForce,
+        display_qty: Option<Qty>,
+    ) -> Self {
+        let mut order = Self {
+            id,
+            owner,
+            side,
+            qty: Qty::ZERO,
+            price,
+            tif,
+            hidden_qty: qty,
+            display_qty,
+            timestamp: DateTime::default(),
+            all_or_none: false,
+            post_only: false,
+        };
+        order.refresh();
+        order
+    }
+
+    /// Displayed and hidden quantity
+    #[inline(always)]
+    pub fn open_qty(&self) -> Qty {
+        self.qty + self.hidden_qty
+    }
+
+    /// Moves the next slice of an iceberg reserve to the displayed quantity
+    #[inline]
+    pub fn refresh(&mut self) {
+        let slice = match self.display_qty {
+            Some(display_qty) => display_qty.min(self.hidden_qty),
+            None => self.hidden_qty,
+        };
+        self.qty += slice;
+        self.hidden_qty -= slice;
+    }
+}
+
+/// Resting order as shown in a market-by-order view
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L3Order {
+    pub id: Uuid,
+    pub owner: u64,
+    pub side: Side,
+    pub price: Price,
+    /// Displayed quantity
+    pub qty: Qty,
+    pub hidden_qty: Qty,
+    /// Place in the queue of its level, 0 is the next order to trade
+    pub position: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Orders of a price level in priority order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L3Level {
+    pub price: Price,
+    pub orders: Vec<L3Order>,
+}
+
+/// Every resting order of the book, levels start from the best price of each side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L3Snapshot {
+    pub timestamp: DateTime<Utc>,
+    pub asks: Vec<L3Level>,
+    pub bids: Vec<L3Level>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -91,8 +519,10 @@ pub struct BookDepth {
     pub bids: Vec<BookLevel>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize)]
 pub struct BookLevel {
-    pub price: f64,
-    pub qty: f64,
+    pub price: Price,
+    /// Displayed quantity, the reserve of iceberg orders is left out
+    pub qty: Qty,
+    pub orders: usize,
 }
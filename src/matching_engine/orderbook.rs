@@ -1,63 +1,198 @@
-use ordered_float::OrderedFloat;
+use chrono::{DateTime, Utc};
+use indexmap::{IndexMap, IndexSet};
 use std::collections::BTreeMap;
+use std::ops::Bound;
 use uuid::Uuid;
 
-use crate::matching_engine::arena::OrderArena;
-use crate::matching_engine::models::{FillMetadata, OrderEvent, OrderType, Side, Trade};
+use crate::matching_engine::arena::{LevelQueue, OrderArena};
+use crate::matching_engine::auction::{equilibrium, Equilibrium};
+use crate::matching_engine::clock::Clock;
+use crate::matching_engine::fees::{FeeEngine, FeeSchedule};
+use crate::matching_engine::market_data::{MarketData, MarketDataFeed, MarketDataMessage};
+use crate::matching_engine::matching::{Fifo, MatchingPolicy};
+use crate::matching_engine::models::{
+    FillMetadata, LimitOrder, OrderEvent, OrderType, PostOnlyMode, Price, PriceBand, Qty,
+    RejectReason, Side, StpMode, TimeInForce, Trade,
+};
+use crate::matching_engine::snapshot::BookSnapshot;
+use crate::matching_engine::tape::{TradeStats, TradeTape};
+use crate::matching_engine::triggers::{StopOrder, TriggerBook};
 
-use super::models::{BookDepth, BookLevel};
+use super::models::{BookDepth, BookLevel, L3Level, L3Order, L3Snapshot};
 
 const DEFAULT_ARENA_CAPACITY: usize = 1_000_000;
-const DEFAULT_QUEUE_CAPACITY: usize = 100_000;
+const DEFAULT_TAPE_CAPACITY: usize = 1_000;
+
+/// Incoming order while it is being matched
+#[derive(Debug)]
+struct Taker {
+    id: Uuid,
+    owner: u64,
+    side: Side,
+    /// Quantity left to match
+    qty: Qty,
+    /// Set when self-trade prevention cancels the incoming order
+    canceled: bool,
+    /// Set when the book had no room left for the rest of the order
+    no_room: bool,
+}
+
+impl Taker {
+    fn new(id: Uuid, owner: u64, side: Side, qty: Qty) -> Self {
+        Self {
+            id,
+            owner,
+            side,
+            qty,
+            canceled: false,
+            no_room: false,
+        }
+    }
+
+    #[inline(always)]
+    fn is_done(&self) -> bool {
+        self.qty.is_zero() || self.canceled
+    }
+}
 
 #[derive(Debug)]
 pub struct OrderBook {
-    last_trade: Option<Trade>,
-    traded_volume: f64,
-    best_ask: Option<OrderedFloat<f64>>,
-    best_bid: Option<OrderedFloat<f64>>,
-    asks: BTreeMap<OrderedFloat<f64>, Vec<usize>>,
-    bids: BTreeMap<OrderedFloat<f64>, Vec<usize>>,
+    tape: TradeTape,
+    best_ask: Option<Price>,
+    best_bid: Option<Price>,
+    asks: BTreeMap<Price, LevelQueue>,
+    bids: BTreeMap<Price, LevelQueue>,
     arena: OrderArena,
-    default_queue_capacity: usize,
+    feed: MarketDataFeed,
+    /// Resting good-till-date and day orders, an order leaves them as soon as it leaves the
+    /// book
+    expiries: BTreeMap<DateTime<Utc>, IndexSet<Uuid>>,
+    day_orders: IndexSet<Uuid>,
+    post_only_mode: PostOnlyMode,
+    stp_mode: StpMode,
+    market_protection: Option<PriceBand>,
+    policy: Box<dyn MatchingPolicy>,
+    auction: bool,
+    triggers: TriggerBook,
+    cascade: Vec<OrderEvent>,
+    clock: Clock,
+    fees: FeeEngine,
 }
 
 impl Default for OrderBook {
     fn default() -> Self {
-        Self::new(DEFAULT_ARENA_CAPACITY, DEFAULT_QUEUE_CAPACITY)
+        Self::new(DEFAULT_ARENA_CAPACITY)
     }
 }
 
 impl OrderBook {
-    pub fn new(arena_capacity: usize, queue_capacity: usize) -> Self {
+    pub fn new(arena_capacity: usize) -> Self {
         Self {
-            last_trade: None,
-            traded_volume: 0.0,
+            tape: TradeTape::new(DEFAULT_TAPE_CAPACITY),
             best_ask: None,
             best_bid: None,
             asks: BTreeMap::new(),
             bids: BTreeMap::new(),
             arena: OrderArena::new(arena_capacity),
-            default_queue_capacity: queue_capacity,
+            feed: MarketDataFeed::default(),
+            expiries: BTreeMap::new(),
+            day_orders: IndexSet::new(),
+            post_only_mode: PostOnlyMode::default(),
+            stp_mode: StpMode::default(),
+            market_protection: None,
+            policy: Box::new(Fifo),
+            auction: false,
+            triggers: TriggerBook::default(),
+            cascade: Vec::new(),
+            clock: Clock::default(),
+            fees: FeeEngine::default(),
         }
     }
 
-    pub fn get_asks(&self) -> BTreeMap<OrderedFloat<f64>, Vec<usize>> {
-        self.asks.clone()
+    pub fn set_post_only_mode(&mut self, mode: PostOnlyMode) {
+        self.post_only_mode = mode;
+    }
+
+    pub fn set_stp_mode(&mut self, mode: StpMode) {
+        self.stp_mode = mode;
+    }
+
+    /// Protection band of market orders that do not set their own, none by default
+    pub fn set_market_protection(&mut self, band: Option<PriceBand>) {
+        self.market_protection = band;
+    }
+
+    /// Maker and taker fees charged on every fill, none by default
+    pub fn set_fee_schedule(&mut self, schedule: FeeSchedule) {
+        self.fees.set_schedule(schedule);
+    }
+
+    #[inline(always)]
+    pub fn fee_schedule(&self) -> &FeeSchedule {
+        self.fees.schedule()
+    }
+
+    /// Lots traded by an owner over the last 30 days, which sets its fee tier
+    pub fn trader_volume(&self, owner: u64) -> Qty {
+        self.fees.volume(owner, self.clock.now())
+    }
+
+    /// Sets where the entry timestamps of resting orders come from, the system clock by default
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    #[inline(always)]
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Moves the book to a simulated time, switching to a manual clock if needed
+    pub fn set_time(&mut self, now: DateTime<Utc>) {
+        self.clock = Clock::Manual(now);
+    }
+
+    /// Turns the market data feed on or off, it is off by default. Messages pile up until
+    /// they are drained.
+    pub fn set_market_data(&mut self, enabled: bool) {
+        self.feed.set_enabled(enabled);
+    }
+
+    /// Takes the market data messages published since the last call
+    pub fn drain_market_data(&mut self) -> Vec<MarketDataMessage> {
+        self.feed.drain()
+    }
+
+    /// Caps the number of resting orders. An order that would rest beyond it is rejected if it
+    /// did not trade, otherwise its remaining quantity is canceled. Unbounded by default.
+    pub fn set_max_orders(&mut self, max_orders: Option<usize>) {
+        self.arena.set_max_orders(max_orders);
+    }
+
+    /// Number of orders resting in the book
+    #[inline(always)]
+    pub fn open_orders(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Sets how incoming orders are split between the orders of a price level,
+    /// price-time priority by default
+    pub fn set_matching_policy(&mut self, policy: impl MatchingPolicy + 'static) {
+        self.policy = Box::new(policy);
     }
 
     #[inline(always)]
-    pub fn best_ask(&self) -> Option<OrderedFloat<f64>> {
+    pub fn best_ask(&self) -> Option<Price> {
         self.best_ask
     }
 
     #[inline(always)]
-    pub fn best_bid(&self) -> Option<OrderedFloat<f64>> {
+    pub fn best_bid(&self) -> Option<Price> {
         self.best_bid
     }
 
     #[inline(always)]
-    pub fn spread(&self) -> Option<OrderedFloat<f64>> {
+    pub fn spread(&self) -> Option<Price> {
         match (self.best_bid, self.best_ask) {
             (Some(b), Some(a)) => Some(a - b),
             _ => None,
@@ -66,84 +201,333 @@ impl OrderBook {
 
     #[inline(always)]
     pub fn last_trade(&self) -> Option<Trade> {
-        self.last_trade
+        self.tape.last_trade()
+    }
+
+    /// Volume traded since the start of the session
+    #[inline(always)]
+    pub fn traded_volume(&self) -> Qty {
+        self.tape.stats().volume
+    }
+
+    /// Last price, VWAP, high, low, volume and number of trades since the start of the session
+    #[inline(always)]
+    pub fn trade_stats(&self) -> TradeStats {
+        self.tape.stats()
+    }
+
+    /// Most recent trades, oldest first
+    pub fn recent_trades(&self) -> impl DoubleEndedIterator<Item = &Trade> {
+        self.tape.recent()
     }
 
+    /// Number of recent trades kept, 1000 by default
+    pub fn set_tape_capacity(&mut self, capacity: usize) {
+        self.tape.set_capacity(capacity);
+    }
+
+    /// Starts new trade statistics, called when a new session opens
+    pub fn reset_trade_stats(&mut self) {
+        self.tape.reset_stats();
+    }
+
+    /// Events caused by the last `execute` call on other orders than the incoming one,
+    /// such as triggered stop orders, in the order they happened
     #[inline(always)]
-    pub fn traded_volume(&self) -> f64 {
-        self.traded_volume
+    pub fn cascade(&self) -> &[OrderEvent] {
+        &self.cascade
     }
 
     pub fn execute(&mut self, event: OrderType) -> OrderEvent {
-        let event = self._execute(event);
-        // uncomment to track total_volume, last_trade
-        // match event.clone() {
-        //     OrderEvent::Filled {
-        //         id: _,
-        //         filled_qty,
-        //         fills,
-        //     } => {
-        //         self.traded_volume += filled_qty;
-        //         let last_fill = fills.last().unwrap();
-        //         self.last_trade = Some(Trade {
-        //             total_qty: filled_qty,
-        //             avg_price: fills.iter().map(|fm| fm.price * fm.qty).sum::<f64>() as f64
-        //                 / (filled_qty as f64),
-        //             last_qty: last_fill.qty,
-        //             last_price: last_fill.price,
-        //         });
-        //     }
-        //     OrderEvent::PartiallyFilled {
-        //         id: _,
-        //         filled_qty,
-        //         fills,
-        //     } => {
-        //         self.traded_volume += filled_qty;
-        //         let last_fill = fills.last().unwrap();
-        //         self.last_trade = Some(Trade {
-        //             total_qty: filled_qty,
-        //             avg_price: fills.iter().map(|fm| fm.price * fm.qty).sum::<f64>() as f64
-        //                 / (filled_qty as f64),
-        //             last_qty: last_fill.qty,
-        //             last_price: last_fill.price,
-        //         });
-        //     }
-        //     _ => {}
-        // }
+        self.cascade.clear();
+        if let Err(reason) = self.validate(&event) {
+            return OrderEvent::Rejected {
+                id: event.id(),
+                reason,
+            };
+        }
+        let mut event = self._execute(event);
+        self.record_trade(event.fills_mut());
+        self.trigger_stops();
+        self.flush_market_data();
         event
     }
 
+    /// Publishes the levels changed by the last call
+    fn flush_market_data(&mut self) {
+        let (asks, bids) = (&self.asks, &self.bids);
+        self.feed.flush_levels(|side, price| {
+            let levels = match side {
+                Side::Ask => asks,
+                Side::Bid => bids,
+            };
+            levels.get(&price).map(|queue| (queue.qty(), queue.len()))
+        });
+    }
+
+    /// Checks an incoming order before it touches the book
+    fn validate(&self, event: &OrderType) -> Result<(), RejectReason> {
+        match *event {
+            OrderType::Market {
+                id,
+                qty,
+                protection,
+                min_qty,
+                ..
+            } => {
+                self.check_new_id(id)?;
+                Self::check_qty(qty)?;
+                Self::check_min_qty(min_qty, qty)?;
+                match protection {
+                    Some(PriceBand::Ticks(width) | PriceBand::BasisPoints(width)) if width <= 0 => {
+                        Err(RejectReason::InvalidProtection)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            OrderType::Limit {
+                id,
+                qty,
+                price,
+                display_qty,
+                min_qty,
+                all_or_none,
+                ..
+            } => {
+                self.check_new_id(id)?;
+                Self::check_qty(qty)?;
+                Self::check_price(price)?;
+                Self::check_min_qty(min_qty, qty)?;
+                match display_qty {
+                    Some(display_qty) if display_qty <= Qty::ZERO || all_or_none => {
+                        Err(RejectReason::InvalidDisplayQty)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            OrderType::Stop {
+                id,
+                qty,
+                stop_price,
+                ..
+            } => {
+                self.check_new_id(id)?;
+                Self::check_qty(qty)?;
+                Self::check_price(stop_price)
+            }
+            OrderType::StopLimit {
+                id,
+                qty,
+                stop_price,
+                price,
+                ..
+            } => {
+                self.check_new_id(id)?;
+                Self::check_qty(qty)?;
+                Self::check_price(stop_price)?;
+                Self::check_price(price)
+            }
+            OrderType::Cancel { id } => match self.is_open(id) {
+                true => Ok(()),
+                false => Err(RejectReason::UnknownOrder),
+            },
+            OrderType::Modify {
+                id,
+                new_price,
+                new_qty,
+            } => {
+                // only resting limit orders can be modified, a quantity of zero cancels them
+                if self.arena.get(id).is_none() {
+                    return Err(RejectReason::UnknownOrder);
+                }
+                if new_qty < Qty::ZERO || new_qty > Qty::MAX {
+                    return Err(RejectReason::InvalidQty);
+                }
+                Self::check_price(new_price)
+            }
+        }
+    }
+
+    /// Whether the order is resting in the book or waiting for its stop price
+    fn is_open(&self, id: Uuid) -> bool {
+        self.arena.get(id).is_some() || self.triggers.contains(id)
+    }
+
+    fn check_new_id(&self, id: Uuid) -> Result<(), RejectReason> {
+        match self.is_open(id) {
+            true => Err(RejectReason::DuplicateOrderId),
+            false => Ok(()),
+        }
+    }
+
+    fn check_qty(qty: Qty) -> Result<(), RejectReason> {
+        match qty > Qty::ZERO && qty <= Qty::MAX {
+            true => Ok(()),
+            false => Err(RejectReason::InvalidQty),
+        }
+    }
+
+    fn check_min_qty(min_qty: Option<Qty>, qty: Qty) -> Result<(), RejectReason> {
+        match min_qty {
+            Some(min_qty) if min_qty <= Qty::ZERO || min_qty > qty => {
+                Err(RejectReason::InvalidMinQty)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_price(price: Price) -> Result<(), RejectReason> {
+        match price > Price::ZERO && price <= Price::MAX {
+            true => Ok(()),
+            false => Err(RejectReason::InvalidPrice),
+        }
+    }
+
+    /// Charges the fees of new fills and prints them on the tape
+    fn record_trade(&mut self, fills: &mut [FillMetadata]) {
+        let timestamp = self.clock.now();
+        for fill in fills {
+            self.fees.charge(fill, timestamp);
+            let trade = self.tape.record(fill, timestamp);
+            self.feed.publish(MarketData::Trade {
+                id: trade.id,
+                price: trade.price,
+                qty: trade.qty,
+                aggressor: trade.aggressor,
+                taker: trade.taker,
+                maker: trade.maker,
+            });
+        }
+    }
+
+    /// Sends triggered stop orders to matching until the last trade price triggers no more
+    fn trigger_stops(&mut self) {
+        while let Some(last_price) = self.tape.last_trade().map(|trade| trade.price) {
+            let triggered = self.triggers.take_triggered(last_price);
+            if triggered.is_empty() {
+                break;
+            }
+            for stop in triggered {
+                self.cascade.push(OrderEvent::Triggered { id: stop.id });
+                let mut event = self._execute(stop.into_order());
+                self.record_trade(event.fills_mut());
+                self.cascade.push(event);
+            }
+        }
+    }
+
     fn _execute(&mut self, event: OrderType) -> OrderEvent {
         match event {
-            OrderType::Market { id, side, qty } => {
-                let (fills, partial, filled_qty) = self.market(id, side, qty);
-                if fills.is_empty() {
-                    OrderEvent::Unfilled { id }
-                } else if partial {
-                    OrderEvent::PartiallyFilled {
+            OrderType::Market {
+                id,
+                owner,
+                side,
+                qty,
+                protection,
+                min_qty,
+            } => {
+                if self.auction {
+                    return OrderEvent::Rejected {
                         id,
-                        filled_qty,
-                        fills,
+                        reason: RejectReason::NotAllowedInAuction,
+                    };
+                }
+                let band = protection.or(self.market_protection);
+                let touch = match side {
+                    Side::Bid => self.best_ask,
+                    Side::Ask => self.best_bid,
+                };
+                let limit_price = band.zip(touch).map(|(band, touch)| band.limit(side, touch));
+                if let Some(min_qty) = min_qty {
+                    if self.fillable_qty(side, owner, qty, limit_price) < min_qty {
+                        return OrderEvent::Killed { id };
                     }
-                } else {
-                    OrderEvent::Filled {
-                        id,
-                        filled_qty,
-                        fills,
+                }
+                let (fills, taker) = self.market(id, owner, side, qty, limit_price);
+                let filled_qty = Self::filled_qty(&fills);
+                // orders inside the band the market order passed by do not make it protected
+                let beyond_band = |limit_price: Price| match side {
+                    Side::Bid => self
+                        .asks
+                        .range((Bound::Excluded(limit_price), Bound::Unbounded))
+                        .next()
+                        .is_some(),
+                    Side::Ask => self.bids.range(..limit_price).next().is_some(),
+                };
+                match limit_price {
+                    // liquidity is left beyond the band
+                    Some(limit_price) if !taker.is_done() && beyond_band(limit_price) => {
+                        OrderEvent::Protected {
+                            id,
+                            filled_qty,
+                            canceled_qty: qty - filled_qty,
+                            limit_price,
+                            fills,
+                        }
                     }
+                    _ => Self::immediate_event(id, qty, filled_qty, fills),
                 }
             }
             OrderType::Limit {
                 id,
+                owner,
                 side,
                 qty,
                 price,
+                tif,
+                post_only,
+                display_qty,
+                min_qty,
+                all_or_none,
             } => {
-                let (fills, partial, filled_qty) = self.limit(id, side, qty, price);
+                if self.auction && (!tif.is_resting() || min_qty.is_some()) {
+                    return OrderEvent::Rejected {
+                        id,
+                        reason: RejectReason::NotAllowedInAuction,
+                    };
+                }
+                let repriced = match post_only {
+                    true => match self.post_only_price(side, price) {
+                        Ok(repriced) => repriced,
+                        Err(reason) => return OrderEvent::Rejected { id, reason },
+                    },
+                    false => None,
+                };
+                let price = repriced.unwrap_or(price);
+                let required = match tif {
+                    TimeInForce::FillOrKill => Some(qty),
+                    _ => min_qty,
+                };
+                if let Some(required) = required {
+                    if self.fillable_qty(side, owner, qty, Some(price)) < required {
+                        return OrderEvent::Killed { id };
+                    }
+                }
+                let (fills, taker) = self.limit(
+                    id,
+                    owner,
+                    side,
+                    qty,
+                    price,
+                    tif,
+                    display_qty,
+                    all_or_none,
+                    post_only,
+                );
+                let filled_qty = Self::filled_qty(&fills);
 
-                if fills.is_empty() {
+                if taker.no_room && fills.is_empty() {
+                    OrderEvent::Rejected {
+                        id,
+                        reason: RejectReason::ArenaFull,
+                    }
+                } else if !tif.is_resting() || taker.canceled {
+                    Self::immediate_event(id, qty, filled_qty, fills)
+                } else if repriced.is_some() {
+                    OrderEvent::Repriced { id, price }
+                } else if fills.is_empty() {
                     OrderEvent::Placed { id }
-                } else if partial {
+                } else if taker.qty > Qty::ZERO {
                     OrderEvent::PartiallyFilled {
                         id,
                         filled_qty,
@@ -157,282 +541,2062 @@ impl OrderBook {
                     }
                 }
             }
+            OrderType::Stop {
+                id,
+                owner,
+                side,
+                qty,
+                stop_price,
+            } => {
+                self.triggers.insert(StopOrder {
+                    id,
+                    owner,
+                    side,
+                    qty,
+                    stop_price,
+                    limit: None,
+                });
+                OrderEvent::Placed { id }
+            }
+            OrderType::StopLimit {
+                id,
+                owner,
+                side,
+                qty,
+                stop_price,
+                price,
+                tif,
+            } => {
+                self.triggers.insert(StopOrder {
+                    id,
+                    owner,
+                    side,
+                    qty,
+                    stop_price,
+                    limit: Some((price, tif)),
+                });
+                OrderEvent::Placed { id }
+            }
             OrderType::Cancel { id } => {
                 self.cancel(id);
                 OrderEvent::Canceled { id }
             }
+            OrderType::Modify {
+                id,
+                new_price,
+                new_qty,
+            } => self.modify(id, new_price, new_qty),
         }
     }
 
     fn cancel(&mut self, id: Uuid) -> bool {
-        if let Some((price, idx)) = self.arena.get(id) {
-            if let Some(queue) = self.asks.get_mut(&OrderedFloat(price)) {
-                if let Some(i) = queue.iter().position(|i| *i == idx) {
-                    queue.remove(i);
-                    self.update_best_ask();
+        if self.triggers.cancel(id) {
+            return true;
+        }
+        match self.arena.get(id) {
+            Some(idx) => {
+                self.remove_order(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn modify(&mut self, id: Uuid, new_price: Price, new_qty: Qty) -> OrderEvent {
+        let idx = match self.arena.get(id) {
+            Some(idx) => idx,
+            None => {
+                return OrderEvent::Rejected {
+                    id,
+                    reason: RejectReason::UnknownOrder,
+                }
+            }
+        };
+        if new_qty <= Qty::ZERO {
+            self.cancel(id);
+            return OrderEvent::Canceled { id };
+        }
+
+        let order = &self.arena[idx];
+        if new_price == order.price && new_qty <= order.open_qty() {
+            // reducing the quantity keeps the time priority, icebergs give up their reserve first
+            let levels = match order.side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            if let Some(queue) = levels.get_mut(&new_price) {
+                Self::update_order(&mut self.arena, &mut self.feed, queue, idx, |order| {
+                    let reduce_by = order.open_qty() - new_qty;
+                    let from_hidden = reduce_by.min(order.hidden_qty);
+                    order.hidden_qty -= from_hidden;
+                    order.qty -= reduce_by - from_hidden;
+                });
+            }
+            return OrderEvent::Modified {
+                id,
+                price: new_price,
+                qty: new_qty,
+                fills: Vec::new(),
+            };
+        }
+
+        // a new price or a bigger quantity sends the order to the back of the queue,
+        // it may also cross the book at the new price
+        let (owner, side, tif, display_qty, all_or_none, post_only) = (
+            order.owner,
+            order.side,
+            order.tif,
+            order.display_qty,
+            order.all_or_none,
+            order.post_only,
+        );
+        // a post-only order is checked before it leaves its level, it is kept as it was if the
+        // new price is rejected
+        let new_price = match post_only {
+            true => match self.post_only_price(side, new_price) {
+                Ok(repriced) => repriced.unwrap_or(new_price),
+                Err(reason) => return OrderEvent::Rejected { id, reason },
+            },
+            false => new_price,
+        };
+        self.remove_order(idx);
+        let (fills, taker) = self.limit(
+            id,
+            owner,
+            side,
+            new_qty,
+            new_price,
+            tif,
+            display_qty,
+            all_or_none,
+            post_only,
+        );
+        OrderEvent::Modified {
+            id,
+            price: new_price,
+            qty: if taker.canceled { Qty::ZERO } else { taker.qty },
+            fills,
+        }
+    }
+
+    /// Unlinks a resting order from its level and drops it from the arena
+    fn remove_order(&mut self, idx: usize) {
+        self.remove_from_queue(idx);
+        Self::delete_order(
+            &mut self.arena,
+            &mut self.expiries,
+            &mut self.day_orders,
+            idx,
+        );
+    }
+
+    /// Drops an order unlinked from its level, along with its place in the expiry sweeps
+    fn delete_order(
+        arena: &mut OrderArena,
+        expiries: &mut BTreeMap<DateTime<Utc>, IndexSet<Uuid>>,
+        day_orders: &mut IndexSet<Uuid>,
+        idx: usize,
+    ) {
+        let (id, tif) = (arena[idx].id, arena[idx].tif);
+        match tif {
+            TimeInForce::GoodTillDate(expiry) => {
+                if let Some(ids) = expiries.get_mut(&expiry) {
+                    ids.swap_remove(&id);
+                    if ids.is_empty() {
+                        expiries.remove(&expiry);
+                    }
                 }
             }
-            if let Some(queue) = self.bids.get_mut(&OrderedFloat(price)) {
-                if let Some(i) = queue.iter().position(|i| *i == idx) {
-                    queue.remove(i);
+            TimeInForce::Day => {
+                day_orders.swap_remove(&id);
+            }
+            _ => {}
+        }
+        arena.delete(&id);
+    }
+
+    /// Unlinks an order from its level, the level is removed as soon as it is empty
+    fn remove_from_queue(&mut self, idx: usize) {
+        let (side, price) = (self.arena[idx].side, self.arena[idx].price);
+        let levels = match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        };
+        if let Some(queue) = levels.get_mut(&price) {
+            Self::unlink(&mut self.arena, &mut self.feed, queue, idx);
+            if queue.is_empty() {
+                levels.remove(&price);
+                match side {
+                    Side::Ask => self.update_best_ask(),
+                    Side::Bid => self.update_best_bid(),
                 }
-                self.update_best_bid();
             }
         }
-        self.arena.delete(&id)
     }
 
-    fn market(&mut self, id: Uuid, side: Side, qty: f64) -> (Vec<FillMetadata>, bool, f64) {
+    fn market(
+        &mut self,
+        id: Uuid,
+        owner: u64,
+        side: Side,
+        qty: Qty,
+        limit_price: Option<Price>,
+    ) -> (Vec<FillMetadata>, Taker) {
         let mut fills = Vec::new();
+        let mut taker = Taker::new(id, owner, side, qty);
 
-        let remaining_qty = match side {
-            Side::Bid => self.match_with_asks(id, qty, &mut fills, None),
-            Side::Ask => self.match_with_bids(id, qty, &mut fills, None),
+        match side {
+            Side::Bid => self.match_with_asks(&mut taker, &mut fills, limit_price),
+            Side::Ask => self.match_with_bids(&mut taker, &mut fills, limit_price),
         };
 
-        let partial = remaining_qty > 0.0;
-
-        (fills, partial, qty - remaining_qty)
+        (fills, taker)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn limit(
         &mut self,
         id: Uuid,
+        owner: u64,
         side: Side,
-        qty: f64,
-        price: f64,
-    ) -> (Vec<FillMetadata>, bool, f64) {
-        let mut partial = false;
-        let remaining_qty;
+        qty: Qty,
+        price: Price,
+        tif: TimeInForce,
+        display_qty: Option<Qty>,
+        all_or_none: bool,
+        post_only: bool,
+    ) -> (Vec<FillMetadata>, Taker) {
         let mut fills: Vec<FillMetadata> = Vec::new();
+        let mut taker = Taker::new(id, owner, side, qty);
+
+        // orders accumulate without matching during an auction, all-or-none orders only
+        // match if they fill completely
+        if !self.auction
+            && (!all_or_none || self.fillable_qty(side, owner, qty, Some(price)) >= qty)
+        {
+            match side {
+                Side::Bid => self.match_with_asks(&mut taker, &mut fills, Some(price)),
+                Side::Ask => self.match_with_bids(&mut taker, &mut fills, Some(price)),
+            };
+        }
+        if taker.qty > Qty::ZERO && !taker.canceled && tif.is_resting() {
+            if self.arena.is_full() {
+                // the capacity only bounds what rests, the remaining quantity is canceled
+                taker.canceled = true;
+                taker.no_room = true;
+            } else {
+                let mut order =
+                    LimitOrder::new(id, owner, side, price, taker.qty, tif, display_qty);
+                order.all_or_none = all_or_none;
+                order.post_only = post_only;
+                self.rest(order);
+            }
+        }
+
+        (fills, taker)
+    }
 
+    /// Stamps an order with its entry time and appends it to its level
+    fn rest(&mut self, mut order: LimitOrder) {
+        order.timestamp = self.clock.now();
+        let (id, side, price, tif) = (order.id, order.side, order.price, order.tif);
+        let index = self.arena.insert(order);
         match side {
             Side::Bid => {
-                remaining_qty = self.match_with_asks(id, qty, &mut fills, Some(price));
-                if remaining_qty > 0.0 {
-                    partial = true;
-                    let index = self.arena.insert(id, price, remaining_qty);
-                    let queue_capacity = self.default_queue_capacity;
-                    self.bids
-                        .entry(OrderedFloat(price))
-                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
-                        .push(index);
-                    match self.best_bid {
-                        None => {
-                            self.best_bid = Some(OrderedFloat(price));
-                        }
-                        Some(b) if price > *b => {
-                            self.best_bid = Some(OrderedFloat(price));
-                        }
-                        _ => {}
-                    };
-                }
+                let queue = self.bids.entry(price).or_default();
+                Self::push_back(&mut self.arena, &mut self.feed, queue, index);
+                match self.best_bid {
+                    None => {
+                        self.best_bid = Some(price);
+                    }
+                    Some(b) if price > b => {
+                        self.best_bid = Some(price);
+                    }
+                    _ => {}
+                };
             }
             Side::Ask => {
-                remaining_qty = self.match_with_bids(id, qty, &mut fills, Some(price));
-                if remaining_qty > 0.0 {
-                    partial = true;
-                    let index = self.arena.insert(id, price, remaining_qty);
-                    if let Some(a) = self.best_ask {
-                        if price < *a {
-                            self.best_ask = Some(OrderedFloat(price));
-                        }
+                let queue = self.asks.entry(price).or_default();
+                Self::push_back(&mut self.arena, &mut self.feed, queue, index);
+                match self.best_ask {
+                    None => {
+                        self.best_ask = Some(price);
                     }
-                    let queue_capacity = self.default_queue_capacity;
-                    self.asks
-                        .entry(OrderedFloat(price))
-                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
-                        .push(index);
-                    match self.best_ask {
-                        None => {
-                            self.best_ask = Some(OrderedFloat(price));
-                        }
-                        Some(a) if price < *a => {
-                            self.best_ask = Some(OrderedFloat(price));
-                        }
-                        _ => {}
-                    };
+                    Some(a) if price < a => {
+                        self.best_ask = Some(price);
+                    }
+                    _ => {}
+                };
+            }
+        }
+        match tif {
+            TimeInForce::GoodTillDate(expiry) => {
+                self.expiries.entry(expiry).or_default().insert(id);
+            }
+            TimeInForce::Day => {
+                self.day_orders.insert(id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Price a post-only order on `side` rests at instead of `price`, one tick behind the
+    /// opposite touch when it would cross in reprice mode. `None` if it does not cross.
+    fn post_only_price(&self, side: Side, price: Price) -> Result<Option<Price>, RejectReason> {
+        let touch = match self.crossing_price(side, price) {
+            Some(touch) => touch,
+            None => return Ok(None),
+        };
+        match self.post_only_mode {
+            PostOnlyMode::Reject => Err(RejectReason::WouldCross),
+            PostOnlyMode::Reprice => {
+                let price = match side {
+                    Side::Bid => touch - Price(1),
+                    Side::Ask => touch + Price(1),
+                };
+                // a touch at the first or last valid tick leaves no price to rest at
+                Self::check_price(price)?;
+                Ok(Some(price))
+            }
+        }
+    }
+
+    /// Opposite touch if an order at `price` would cross it
+    fn crossing_price(&self, side: Side, price: Price) -> Option<Price> {
+        if self.auction {
+            return None;
+        }
+        match side {
+            Side::Bid => self.best_ask.filter(|ask| price >= *ask),
+            Side::Ask => self.best_bid.filter(|bid| price <= *bid),
+        }
+    }
+
+    /// Quantity an incoming order of `qty` could fill right away up to `limit_price`, walking
+    /// the opposite side as the matching does. All-or-none orders only count if they fit in
+    /// what is left, and orders of the same owner stop or shrink the count the way self-trade
+    /// prevention would.
+    fn fillable_qty(&self, side: Side, owner: u64, qty: Qty, limit_price: Option<Price>) -> Qty {
+        let (mut fillable, mut left) = (Qty::ZERO, qty);
+        let levels: Box<dyn Iterator<Item = (&Price, &LevelQueue)>> = match (side, limit_price) {
+            (Side::Bid, Some(price)) => Box::new(self.asks.range(..=price)),
+            (Side::Bid, None) => Box::new(self.asks.iter()),
+            (Side::Ask, Some(price)) => Box::new(self.bids.range(price..).rev()),
+            (Side::Ask, None) => Box::new(self.bids.iter().rev()),
+        };
+        for (_price, queue) in levels {
+            for idx in self.arena.iter(queue) {
+                let order = &self.arena[idx];
+                let open_qty = order.open_qty();
+                if order.all_or_none && open_qty > left {
+                    continue;
+                }
+                let traded = match self.stp_mode {
+                    _ if order.owner != owner => open_qty.min(left),
+                    StpMode::Disabled => open_qty.min(left),
+                    StpMode::CancelOldest => Qty::ZERO,
+                    StpMode::CancelNewest | StpMode::CancelBoth => return fillable,
+                    StpMode::DecrementAndCancel => {
+                        left -= open_qty.min(left);
+                        Qty::ZERO
+                    }
+                };
+                fillable += traded;
+                left -= traded;
+                if left.is_zero() {
+                    return fillable;
                 }
             }
         }
+        fillable
+    }
 
-        (fills, partial, qty - remaining_qty)
+    /// Expires the good-till-date orders whose expiry is at or before `now`
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<OrderEvent> {
+        let mut events = Vec::new();
+        while let Some(entry) = self.expiries.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            let ids = entry.remove();
+            events.extend(ids.into_iter().filter_map(|id| self.expire_order(id)));
+        }
+        self.flush_market_data();
+        events
     }
 
-    fn match_with_asks(
-        &mut self,
+    /// Expires every resting day order, meant to be called at the end of the session
+    pub fn expire_day_orders(&mut self) -> Vec<OrderEvent> {
+        let ids = std::mem::take(&mut self.day_orders);
+        let events = ids
+            .into_iter()
+            .filter_map(|id| self.expire_order(id))
+            .collect();
+        self.flush_market_data();
+        events
+    }
+
+    fn expire_order(&mut self, id: Uuid) -> Option<OrderEvent> {
+        let idx = self.arena.get(id)?;
+        let qty = self.arena[idx].open_qty();
+        self.cancel(id);
+        Some(OrderEvent::Expired { id, qty })
+    }
+
+    fn filled_qty(fills: &[FillMetadata]) -> Qty {
+        fills.iter().fold(Qty::ZERO, |acc, fm| acc + fm.qty)
+    }
+
+    fn immediate_event(
         id: Uuid,
-        qty: f64,
-        fills: &mut Vec<FillMetadata>,
-        limit_price: Option<f64>,
-    ) -> f64 {
-        let mut remaining_qty = qty;
-        let mut update_bid_ask = false;
-        for (ask_price, queue) in self.asks.iter_mut() {
-            if queue.is_empty() {
-                continue;
+        qty: Qty,
+        filled_qty: Qty,
+        fills: Vec<FillMetadata>,
+    ) -> OrderEvent {
+        if fills.is_empty() {
+            OrderEvent::Unfilled { id }
+        } else if filled_qty < qty {
+            OrderEvent::PartiallyFilledCanceled {
+                id,
+                filled_qty,
+                canceled_qty: qty - filled_qty,
+                fills,
             }
-            if (update_bid_ask || self.best_ask.is_none()) && !queue.is_empty() {
-                self.best_ask = Some(*ask_price);
-                update_bid_ask = false;
+        } else {
+            OrderEvent::Filled {
+                id,
+                filled_qty,
+                fills,
             }
+        }
+    }
+
+    fn match_with_asks(
+        &mut self,
+        taker: &mut Taker,
+        fills: &mut Vec<FillMetadata>,
+        limit_price: Option<Price>,
+    ) {
+        // levels passed by because of all-or-none orders
+        let mut after = Bound::Unbounded;
+        while !taker.is_done() {
+            // the level is copied out while it is matched and written back after
+            let (price, mut level) = match self.asks.range((after, Bound::Unbounded)).next() {
+                Some((price, level)) => (*price, *level),
+                None => break,
+            };
             if let Some(lp) = limit_price {
-                if lp < **ask_price {
+                if lp < price {
                     break;
                 }
             }
-            if remaining_qty == 0.0 {
-                break;
+            let skipped = self.process_queue(&mut level, taker, fills);
+            if level.is_empty() {
+                self.asks.remove(&price);
+                continue;
             }
-            let filled_qty =
-                Self::process_queue(&mut self.arena, queue, remaining_qty, id, Side::Bid, fills);
-            if queue.is_empty() {
-                update_bid_ask = true;
+            self.asks.insert(price, level);
+            if skipped && !taker.is_done() {
+                after = Bound::Excluded(price);
+            } else {
+                break;
             }
-            remaining_qty -= filled_qty;
         }
 
         self.update_best_ask();
-        remaining_qty
     }
 
     fn match_with_bids(
         &mut self,
-        id: Uuid,
-        qty: f64,
+        taker: &mut Taker,
         fills: &mut Vec<FillMetadata>,
-        limit_price: Option<f64>,
-    ) -> f64 {
-        let mut remaining_qty = qty;
-        let mut update_bid_ask = false;
-        for (bid_price, queue) in self.bids.iter_mut().rev() {
-            if queue.is_empty() {
-                continue;
-            }
-            if (update_bid_ask || self.best_bid.is_none()) && !queue.is_empty() {
-                self.best_bid = Some(*bid_price);
-                update_bid_ask = false;
-            }
+        limit_price: Option<Price>,
+    ) {
+        // levels passed by because of all-or-none orders
+        let mut before = Bound::Unbounded;
+        while !taker.is_done() {
+            // the level is copied out while it is matched and written back after
+            let (price, mut level) = match self.bids.range((Bound::Unbounded, before)).next_back() {
+                Some((price, level)) => (*price, *level),
+                None => break,
+            };
             if let Some(lp) = limit_price {
-                if lp > **bid_price {
+                if lp > price {
                     break;
                 }
             }
-            if remaining_qty == 0.0 {
-                break;
+            let skipped = self.process_queue(&mut level, taker, fills);
+            if level.is_empty() {
+                self.bids.remove(&price);
+                continue;
             }
-            let filled_qty =
-                Self::process_queue(&mut self.arena, queue, remaining_qty, id, Side::Ask, fills);
-            if queue.is_empty() {
-                update_bid_ask = true;
+            self.bids.insert(price, level);
+            if skipped && !taker.is_done() {
+                before = Bound::Excluded(price);
+            } else {
+                break;
             }
-            remaining_qty -= filled_qty;
         }
 
         self.update_best_bid();
-        remaining_qty
+    }
+
+    /// Appends an order to the back of its level and publishes it
+    fn push_back(
+        arena: &mut OrderArena,
+        feed: &mut MarketDataFeed,
+        queue: &mut LevelQueue,
+        idx: usize,
+    ) {
+        let order = &arena[idx];
+        feed.touch(order.side, order.price, queue.state());
+        feed.publish(MarketData::OrderAdd {
+            id: order.id,
+            side: order.side,
+            price: order.price,
+            qty: order.qty,
+        });
+        arena.push_back(queue, idx);
+    }
+
+    /// Removes an order from its level and publishes the deletion
+    fn unlink(
+        arena: &mut OrderArena,
+        feed: &mut MarketDataFeed,
+        queue: &mut LevelQueue,
+        idx: usize,
+    ) {
+        let order = &arena[idx];
+        feed.touch(order.side, order.price, queue.state());
+        feed.publish(MarketData::OrderDelete {
+            id: order.id,
+            side: order.side,
+            price: order.price,
+        });
+        arena.unlink(queue, idx);
+    }
+
+    /// Changes an order in its level and publishes its new displayed quantity
+    fn update_order<R>(
+        arena: &mut OrderArena,
+        feed: &mut MarketDataFeed,
+        queue: &mut LevelQueue,
+        idx: usize,
+        f: impl FnOnce(&mut LimitOrder) -> R,
+    ) -> R {
+        let (before, qty) = (queue.state(), arena[idx].qty);
+        let result = arena.update(queue, idx, f);
+        let order = &arena[idx];
+        // the reserve of an iceberg is not published
+        if order.qty != qty {
+            feed.touch(order.side, order.price, before);
+            feed.publish(MarketData::OrderModify {
+                id: order.id,
+                side: order.side,
+                price: order.price,
+                qty: order.qty,
+            });
+        }
+        result
     }
 
     fn update_best_ask(&mut self) {
-        let mut cur_asks = self.asks.iter().filter(|(_, q)| !q.is_empty());
-        self.best_ask = cur_asks.next().map(|(p, _)| *p);
+        self.best_ask = self.asks.keys().next().copied();
     }
 
     fn update_best_bid(&mut self) {
-        let mut cur_bids = self.bids.iter().rev().filter(|(_, q)| !q.is_empty());
-        self.best_bid = cur_bids.next().map(|(p, _)| *p);
+        self.best_bid = self.bids.keys().next_back().copied();
     }
 
     fn process_queue(
-        arena: &mut OrderArena,
-        opposite_orders: &mut Vec<usize>,
-        remaining_qty: f64,
-        id: Uuid,
-        side: Side,
+        &mut self,
+        opposite_orders: &mut LevelQueue,
+        taker: &mut Taker,
         fills: &mut Vec<FillMetadata>,
-    ) -> f64 {
-        let mut qty_to_fill = remaining_qty;
-        let mut filled_qty = 0.0;
-        let mut filled_index = None;
+    ) -> bool {
+        let (arena, feed, stp_mode) = (&mut self.arena, &mut self.feed, self.stp_mode);
+        let mut skipped = false;
+        // allocations are recomputed when self-trade prevention or an iceberg refresh changes
+        // the level before the incoming order is done
+        while !taker.is_done() && !opposite_orders.is_empty() {
+            // all-or-none orders bigger than the incoming order are passed by
+            let allocations = self.policy.allocate(
+                taker.qty,
+                &mut arena.iter(opposite_orders).map(|idx| {
+                    let order = &arena[idx];
+                    match order.all_or_none && order.qty > taker.qty {
+                        true => Qty::ZERO,
+                        false => order.qty,
+                    }
+                }),
+            );
+            let orders: Vec<usize> = arena
+                .iter(opposite_orders)
+                .take(allocations.len())
+                .collect();
+            let mut progress = false;
+            skipped = false;
+
+            for (order_idx, allocation) in orders.into_iter().zip(allocations) {
+                if arena[order_idx].all_or_none && allocation < arena[order_idx].qty {
+                    skipped = true;
+                    continue;
+                }
+                if allocation.is_zero() {
+                    continue;
+                }
+                progress = true;
+                if arena[order_idx].owner == taker.owner && stp_mode != StpMode::Disabled {
+                    Self::update_order(arena, feed, opposite_orders, order_idx, |order| {
+                        Self::prevent_self_trade(order, taker, stp_mode, &mut self.cascade)
+                    });
+                    if arena[order_idx].open_qty().is_zero() {
+                        Self::unlink(arena, feed, opposite_orders, order_idx);
+                        Self::delete_order(
+                            arena,
+                            &mut self.expiries,
+                            &mut self.day_orders,
+                            order_idx,
+                        );
+                    }
+                    break;
+                }
+                let traded_quantity = allocation.min(taker.qty).min(arena[order_idx].qty);
+                Self::update_order(arena, feed, opposite_orders, order_idx, |order| {
+                    order.qty -= traded_quantity
+                });
+                taker.qty -= traded_quantity;
+                let order = &arena[order_idx];
+                let fill = FillMetadata {
+                    order_1: taker.id,
+                    order_2: order.id,
+                    owner_1: taker.owner,
+                    owner_2: order.owner,
+                    qty: traded_quantity,
+                    price: order.price,
+                    taker_side: taker.side,
+                    total_fill: order.open_qty().is_zero(),
+                    fee_1: 0.0,
+                    fee_2: 0.0,
+                };
+                fills.push(fill);
+
+                if order.qty.is_zero() {
+                    let hidden_qty = order.hidden_qty;
+                    Self::unlink(arena, feed, opposite_orders, order_idx);
+                    // a depleted iceberg slice is refreshed from the reserve at the back of the
+                    // queue, as a new order entered now
+                    if hidden_qty.is_zero() {
+                        Self::delete_order(
+                            arena,
+                            &mut self.expiries,
+                            &mut self.day_orders,
+                            order_idx,
+                        );
+                    } else {
+                        arena[order_idx].refresh();
+                        arena[order_idx].timestamp = self.clock.now();
+                        Self::push_back(arena, feed, opposite_orders, order_idx);
+                    }
+                }
+            }
 
-        for (index, head_order_idx) in opposite_orders.iter_mut().enumerate() {
-            if qty_to_fill == 0.0 {
+            if !progress {
                 break;
             }
-            let head_order = &mut arena[*head_order_idx];
-            let traded_price = head_order.price;
-            let available_qty = head_order.qty;
-            if available_qty == 0.0 {
-                filled_index = Some(index);
-                continue;
+        }
+        skipped
+    }
+
+    /// Applies the self-trade prevention mode to an incoming and a resting order of the same
+    /// owner. Canceled resting orders are left with no quantity for the caller to dequeue.
+    fn prevent_self_trade(
+        resting: &mut LimitOrder,
+        taker: &mut Taker,
+        stp_mode: StpMode,
+        cascade: &mut Vec<OrderEvent>,
+    ) {
+        let (resting_qty, taker_qty) = match stp_mode {
+            StpMode::Disabled => return,
+            StpMode::CancelNewest => (Qty::ZERO, taker.qty),
+            StpMode::CancelOldest => (resting.open_qty(), Qty::ZERO),
+            StpMode::CancelBoth => (resting.open_qty(), taker.qty),
+            StpMode::DecrementAndCancel => {
+                let qty = taker.qty.min(resting.open_qty());
+                (qty, qty)
             }
-            let traded_quantity: f64;
-            let filled;
+        };
 
-            if qty_to_fill >= available_qty {
-                traded_quantity = available_qty;
-                qty_to_fill -= available_qty;
-                filled_index = Some(index);
-                filled = true;
-            } else {
-                traded_quantity = qty_to_fill;
-                qty_to_fill = 0.0;
-                filled = false;
-            }
-            head_order.qty -= traded_quantity;
-            let fill = FillMetadata {
-                order_1: id,
-                order_2: head_order.id,
-                qty: traded_quantity,
-                price: traded_price,
-                taker_side: side,
-                total_fill: filled,
+        if !resting_qty.is_zero() {
+            // the reserve of an iceberg goes first so the displayed part keeps its place
+            let from_hidden = resting_qty.min(resting.hidden_qty);
+            resting.hidden_qty -= from_hidden;
+            resting.qty -= resting_qty - from_hidden;
+            cascade.push(OrderEvent::SelfTradePrevented {
+                id: resting.id,
+                canceled_qty: resting_qty,
+            });
+        }
+        if !taker_qty.is_zero() {
+            taker.qty -= taker_qty;
+            if stp_mode != StpMode::DecrementAndCancel || taker.qty.is_zero() {
+                taker.qty = Qty::ZERO;
+                taker.canceled = true;
+            }
+            cascade.push(OrderEvent::SelfTradePrevented {
+                id: taker.id,
+                canceled_qty: taker_qty,
+            });
+        }
+    }
+
+    /// Starts an auction phase, orders rest without matching until `uncross` is called
+    pub fn begin_auction(&mut self) {
+        self.auction = true;
+    }
+
+    /// Ends an auction phase without matching, crossing orders stay on the book until the next
+    /// `uncross`
+    pub fn end_auction(&mut self) {
+        self.auction = false;
+    }
+
+    #[inline(always)]
+    pub fn in_auction(&self) -> bool {
+        self.auction
+    }
+
+    /// Indicative uncrossing price of the book. The reference price defaults to the last trade.
+    pub fn equilibrium(&self, reference_price: Option<Price>) -> Option<Equilibrium> {
+        self.auction_equilibrium(reference_price).0
+    }
+
+    /// Equilibrium of the book and the all-or-none orders left out of it. An all-or-none order
+    /// the uncross would only fill in part is left out and the price is searched again without
+    /// it, until every all-or-none order that trades fills completely.
+    fn auction_equilibrium(
+        &self,
+        reference_price: Option<Price>,
+    ) -> (Option<Equilibrium>, Vec<usize>) {
+        let reference_price =
+            reference_price.or_else(|| self.tape.last_trade().map(|trade| trade.price));
+        let mut excluded = Vec::new();
+        loop {
+            let level_totals = |levels: &BTreeMap<Price, LevelQueue>, side| {
+                let mut totals: Vec<(Price, Qty)> = levels
+                    .iter()
+                    .map(|(price, queue)| (*price, queue.open_qty()))
+                    .collect();
+                for order in excluded.iter().map(|idx| &self.arena[*idx]) {
+                    if order.side == side {
+                        if let Ok(pos) = totals.binary_search_by_key(&order.price, |level| level.0)
+                        {
+                            totals[pos].1 -= order.open_qty();
+                        }
+                    }
+                }
+                totals
             };
-            fills.push(fill);
-            filled_qty += traded_quantity;
+            let eq = match equilibrium(
+                &level_totals(&self.bids, Side::Bid),
+                &level_totals(&self.asks, Side::Ask),
+                reference_price,
+            ) {
+                Some(eq) => eq,
+                None => return (None, excluded),
+            };
+            let partial: Vec<usize> = [Side::Bid, Side::Ask]
+                .into_iter()
+                .filter_map(|side| self.partially_uncrossed(side, eq.volume, &excluded))
+                .collect();
+            if partial.is_empty() {
+                return (Some(eq), excluded);
+            }
+            excluded.extend(partial);
         }
-        if let Some(index) = filled_index {
-            opposite_orders.drain(0..index + 1);
+    }
+
+    /// First all-or-none order of `side` an uncross of `volume` would only fill in part
+    fn partially_uncrossed(&self, side: Side, volume: Qty, excluded: &[usize]) -> Option<usize> {
+        let levels: Box<dyn Iterator<Item = &LevelQueue>> = match side {
+            Side::Bid => Box::new(self.bids.values().rev()),
+            Side::Ask => Box::new(self.asks.values()),
+        };
+        let mut ahead = Qty::ZERO;
+        for queue in levels {
+            for idx in self.arena.iter(queue) {
+                if ahead >= volume {
+                    return None;
+                }
+                if excluded.contains(&idx) {
+                    continue;
+                }
+                let order = &self.arena[idx];
+                if order.all_or_none && ahead + order.open_qty() > volume {
+                    return Some(idx);
+                }
+                ahead += order.open_qty();
+            }
         }
+        None
+    }
 
-        filled_qty
+    /// Ends the auction phase and executes every crossing order at the equilibrium price.
+    /// Returns one event per order that traded, followed by the events of triggered stops.
+    /// All-or-none orders that would not fill completely keep resting untouched.
+    ///
+    /// `taker_side` of auction fills is the side with more quantity at the equilibrium price,
+    /// and `order_1` is the order of that side, as the incoming order of a continuous fill.
+    pub fn uncross(&mut self, reference_price: Option<Price>) -> Vec<OrderEvent> {
+        self.auction = false;
+        self.cascade.clear();
+        let (eq, excluded) = match self.auction_equilibrium(reference_price) {
+            (Some(eq), excluded) => (eq, excluded),
+            (None, _) => return Vec::new(),
+        };
+        let taker_side = if eq.imbalance >= Qty::ZERO {
+            Side::Bid
+        } else {
+            Side::Ask
+        };
+
+        let mut fills = Vec::new();
+        let mut left = eq.volume;
+        while !left.is_zero() {
+            let (bid_idx, ask_idx) = match (
+                self.front_order(Side::Bid, &excluded),
+                self.front_order(Side::Ask, &excluded),
+            ) {
+                (Some(bid_idx), Some(ask_idx)) => (bid_idx, ask_idx),
+                _ => break,
+            };
+            let qty = left
+                .min(self.arena[bid_idx].open_qty())
+                .min(self.arena[ask_idx].open_qty());
+            left -= qty;
+            for (idx, levels) in [(bid_idx, &mut self.bids), (ask_idx, &mut self.asks)] {
+                if let Some(queue) = levels.get_mut(&self.arena[idx].price) {
+                    Self::update_order(&mut self.arena, &mut self.feed, queue, idx, |order| {
+                        Self::consume(order, qty)
+                    });
+                }
+            }
+
+            let (taker_idx, maker_idx) = match taker_side {
+                Side::Bid => (bid_idx, ask_idx),
+                Side::Ask => (ask_idx, bid_idx),
+            };
+            let (taker, maker) = (&self.arena[taker_idx], &self.arena[maker_idx]);
+            fills.push(FillMetadata {
+                order_1: taker.id,
+                order_2: maker.id,
+                owner_1: taker.owner,
+                owner_2: maker.owner,
+                qty,
+                price: eq.price,
+                taker_side,
+                total_fill: maker.open_qty().is_zero(),
+                fee_1: 0.0,
+                fee_2: 0.0,
+            });
+            for idx in [bid_idx, ask_idx] {
+                if self.arena[idx].open_qty().is_zero() {
+                    self.remove_order(idx);
+                }
+            }
+        }
+
+        self.record_trade(&mut fills);
+        let mut executions: IndexMap<Uuid, Vec<FillMetadata>> = IndexMap::new();
+        for fill in &fills {
+            let (bid, ask) = match fill.taker_side {
+                Side::Bid => (fill.order_1, fill.order_2),
+                Side::Ask => (fill.order_2, fill.order_1),
+            };
+            for id in [bid, ask] {
+                executions.entry(id).or_default().push(*fill);
+            }
+        }
+        let mut events: Vec<OrderEvent> = executions
+            .into_iter()
+            .map(|(id, fills)| {
+                let filled_qty = Self::filled_qty(&fills);
+                if self.arena.get(id).is_some() {
+                    OrderEvent::PartiallyFilled {
+                        id,
+                        filled_qty,
+                        fills,
+                    }
+                } else {
+                    OrderEvent::Filled {
+                        id,
+                        filled_qty,
+                        fills,
+                    }
+                }
+            })
+            .collect();
+        self.trigger_stops();
+        self.flush_market_data();
+        events.extend(self.cascade.iter().cloned());
+        events
+    }
+
+    /// Order of `side` first in priority, passing by the orders left out of the uncross
+    fn front_order(&self, side: Side, excluded: &[usize]) -> Option<usize> {
+        let mut levels: Box<dyn Iterator<Item = &LevelQueue>> = match side {
+            Side::Bid => Box::new(self.bids.values().rev()),
+            Side::Ask => Box::new(self.asks.values()),
+        };
+        levels.find_map(|queue| self.arena.iter(queue).find(|idx| !excluded.contains(idx)))
     }
 
+    /// Takes `qty` from the open quantity of an order, refreshing iceberg slices as needed
+    fn consume(order: &mut LimitOrder, qty: Qty) {
+        let mut left = qty;
+        while !left.is_zero() {
+            let taken = left.min(order.qty);
+            order.qty -= taken;
+            left -= taken;
+            if order.qty.is_zero() {
+                order.refresh();
+            }
+        }
+    }
+
+    /// Displayed quantity and number of orders of the best `levels` price levels of each side
     pub fn depth(&self, levels: usize) -> BookDepth {
-        let mut asks: Vec<BookLevel> = Vec::with_capacity(levels);
-        let mut bids: Vec<BookLevel> = Vec::with_capacity(levels);
+        BookDepth {
+            levels,
+            asks: self.levels(Side::Ask).take(levels).collect(),
+            bids: self.levels(Side::Bid).take(levels).collect(),
+        }
+    }
 
-        for (i, (ask_price, queue)) in self.asks.iter().enumerate() {
-            if i > levels {
-                break;
+    /// Displayed quantity and number of orders resting at a price
+    pub fn level(&self, side: Side, price: Price) -> Option<BookLevel> {
+        let levels = match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        levels
+            .get(&price)
+            .map(|queue| Self::book_level(price, queue))
+    }
+
+    /// Best `levels` price levels of a side with the quantity and number of orders up to and
+    /// including each level
+    pub fn cumulative_depth(&self, side: Side, levels: usize) -> Vec<BookLevel> {
+        let mut total = BookLevel::default();
+        self.levels(side)
+            .take(levels)
+            .map(|level| {
+                total.qty += level.qty;
+                total.orders += level.orders;
+                BookLevel {
+                    price: level.price,
+                    ..total
+                }
+            })
+            .collect()
+    }
+
+    /// Resting orders of a price level in priority order
+    pub fn orders_at(&self, side: Side, price: Price) -> impl Iterator<Item = L3Order> + '_ {
+        let levels = match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        levels
+            .get(&price)
+            .into_iter()
+            .flat_map(|queue| self.l3_orders(queue))
+    }
+
+    /// Resting orders of a side in priority order, from the best price
+    pub fn orders(&self, side: Side) -> impl Iterator<Item = L3Order> + '_ {
+        self.queues(side)
+            .flat_map(|(_price, queue)| self.l3_orders(queue))
+    }
+
+    /// Every resting order of the book, taken at the time of the book clock
+    pub fn l3_snapshot(&self) -> L3Snapshot {
+        let levels = |side| -> Vec<L3Level> {
+            self.queues(side)
+                .map(|(price, queue)| L3Level {
+                    price: *price,
+                    orders: self.l3_orders(queue).collect(),
+                })
+                .collect()
+        };
+        L3Snapshot {
+            timestamp: self.clock.now(),
+            asks: levels(Side::Ask),
+            bids: levels(Side::Bid),
+        }
+    }
+
+    /// Full state of the book, to checkpoint it or start a scenario from it
+    pub fn snapshot(&self) -> BookSnapshot {
+        let orders = |side| -> Vec<LimitOrder> {
+            self.queues(side)
+                .flat_map(|(_, queue)| self.arena.iter(queue))
+                .map(|idx| self.arena[idx].clone())
+                .collect()
+        };
+        BookSnapshot {
+            asks: orders(Side::Ask),
+            bids: orders(Side::Bid),
+            stops: self.triggers.orders().copied().collect(),
+            expiries: self
+                .expiries
+                .iter()
+                .map(|(expiry, ids)| (*expiry, ids.iter().copied().collect()))
+                .collect(),
+            day_orders: self.day_orders.iter().copied().collect(),
+            post_only_mode: self.post_only_mode,
+            stp_mode: self.stp_mode,
+            market_protection: self.market_protection,
+            auction: self.auction,
+            clock: self.clock,
+            max_orders: self.arena.max_orders(),
+            market_data: self.feed.is_enabled(),
+            market_data_seq: self.feed.seq(),
+            tape: self.tape.clone(),
+            fees: self.fees.clone(),
+        }
+    }
+
+    /// Replaces the state of the book with a snapshot. The matching policy and the allocated
+    /// memory of the book are kept, pending market data is dropped.
+    pub fn restore(&mut self, snapshot: BookSnapshot) {
+        self.asks.clear();
+        self.bids.clear();
+        self.arena.clear();
+        self.arena.set_max_orders(snapshot.max_orders);
+        // resting orders are loaded without publishing them
+        self.feed.reset(false, snapshot.market_data_seq);
+        for order in snapshot.asks.into_iter().chain(snapshot.bids) {
+            let levels = match order.side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            let queue = levels.entry(order.price).or_default();
+            let idx = self.arena.insert(order);
+            Self::push_back(&mut self.arena, &mut self.feed, queue, idx);
+        }
+        self.feed
+            .reset(snapshot.market_data, snapshot.market_data_seq);
+        self.best_ask = self.asks.keys().next().copied();
+        self.best_bid = self.bids.keys().next_back().copied();
+
+        self.triggers = TriggerBook::default();
+        for stop in snapshot.stops {
+            self.triggers.insert(stop);
+        }
+        self.expiries = snapshot
+            .expiries
+            .into_iter()
+            .map(|(expiry, ids)| (expiry, ids.into_iter().collect()))
+            .collect();
+        self.day_orders = snapshot.day_orders.into_iter().collect();
+        self.post_only_mode = snapshot.post_only_mode;
+        self.stp_mode = snapshot.stp_mode;
+        self.market_protection = snapshot.market_protection;
+        self.auction = snapshot.auction;
+        self.clock = snapshot.clock;
+        self.tape = snapshot.tape;
+        self.fees = snapshot.fees;
+        self.cascade.clear();
+    }
+
+    fn l3_orders<'a>(&'a self, queue: &LevelQueue) -> impl Iterator<Item = L3Order> + 'a {
+        self.arena
+            .iter(queue)
+            .enumerate()
+            .map(move |(position, idx)| {
+                let order = &self.arena[idx];
+                L3Order {
+                    id: order.id,
+                    owner: order.owner,
+                    side: order.side,
+                    price: order.price,
+                    qty: order.qty,
+                    hidden_qty: order.hidden_qty,
+                    position,
+                    timestamp: order.timestamp,
+                }
+            })
+    }
+
+    /// Levels of a side from the best price
+    fn levels(&self, side: Side) -> impl Iterator<Item = BookLevel> + '_ {
+        self.queues(side)
+            .map(|(price, queue)| Self::book_level(*price, queue))
+    }
+
+    fn queues(&self, side: Side) -> Box<dyn Iterator<Item = (&Price, &LevelQueue)> + '_> {
+        match side {
+            Side::Ask => Box::new(self.asks.iter()),
+            Side::Bid => Box::new(self.bids.iter().rev()),
+        }
+    }
+
+    fn book_level(price: Price, queue: &LevelQueue) -> BookLevel {
+        BookLevel {
+            price,
+            qty: queue.qty(),
+            orders: queue.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::fees::FeeRate;
+    use crate::matching_engine::matching::ProRata;
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use chrono::TimeZone;
+
+    #[test]
+    fn fills_resting_orders_exactly() {
+        let mut ob = OrderBook::new(16);
+        let (ask1, ask2, bid) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        for id in [ask1, ask2] {
+            ob.execute(limit(id, Side::Ask, 3, 10_001));
+        }
+
+        let event = ob.execute(limit(bid, Side::Bid, 4, 10_001));
+        assert!(matches!(
+            event,
+            OrderEvent::Filled {
+                filled_qty: Qty(4),
+                ..
             }
-            let mut qty = 0.0;
-            for idx in queue {
-                qty += self.arena[*idx].qty;
+        ));
+        assert_eq!(ob.best_ask(), Some(Price(10_001)));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(2));
+        assert_eq!(ob.best_bid(), None);
+    }
+
+    #[test]
+    fn cancel_unlinks_orders_and_drops_empty_levels() {
+        let mut ob = OrderBook::new(16);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            ob.execute(limit(*id, Side::Ask, 1, 100));
+        }
+        let worse = Uuid::new_v4();
+        ob.execute(limit(worse, Side::Ask, 1, 101));
+
+        ob.execute(OrderType::Cancel { id: ids[1] });
+        let event = ob.execute(limit(Uuid::new_v4(), Side::Bid, 2, 100));
+        let makers: Vec<Uuid> = event.fills().iter().map(|fill| fill.order_2).collect();
+        assert_eq!(makers, vec![ids[0], ids[2]]);
+        assert_eq!(ob.best_ask(), Some(Price(101)));
+
+        ob.execute(OrderType::Cancel { id: worse });
+        assert_eq!(ob.best_ask(), None);
+        assert!(ob.asks.is_empty());
+    }
+
+    #[test]
+    fn modify_keeps_priority_only_when_reducing_qty() {
+        let mut ob = OrderBook::new(16);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [first, second] {
+            ob.execute(limit(id, Side::Bid, 5, 100));
+        }
+
+        let event = ob.execute(OrderType::Modify {
+            id: first,
+            new_price: Price(100),
+            new_qty: Qty(2),
+        });
+        assert_eq!(
+            event,
+            OrderEvent::Modified {
+                id: first,
+                price: Price(100),
+                qty: Qty(2),
+                fills: vec![],
             }
-            if qty > 0.0 {
-                asks.push(BookLevel {
-                    price: **ask_price,
-                    qty,
-                });
+        );
+        let fills = match ob.execute(OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Ask,
+            qty: Qty(1),
+            protection: None,
+            min_qty: None,
+        }) {
+            OrderEvent::Filled { fills, .. } => fills,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(fills[0].order_2, first);
+
+        // increasing the quantity loses the time priority
+        ob.execute(OrderType::Modify {
+            id: first,
+            new_price: Price(100),
+            new_qty: Qty(4),
+        });
+        let fills = match ob.execute(OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Ask,
+            qty: Qty(6),
+            protection: None,
+            min_qty: None,
+        }) {
+            OrderEvent::Filled { fills, .. } => fills,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(fills[0].order_2, second);
+        assert_eq!(fills[1].order_2, first);
+        assert_eq!(ob.depth(1).bids[0].qty, Qty(3));
+    }
+
+    #[test]
+    fn immediate_orders_never_rest() {
+        let mut ob = OrderBook::new(16);
+        let ask = Uuid::new_v4();
+        ob.execute(limit(ask, Side::Ask, 3, 100));
+
+        let fok = Uuid::new_v4();
+        let event = ob.execute(limit(fok, Side::Bid, 4, 100).tif(TimeInForce::FillOrKill));
+        assert_eq!(event, OrderEvent::Killed { id: fok });
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(3));
+
+        let event = ob
+            .execute(limit(Uuid::new_v4(), Side::Bid, 4, 100).tif(TimeInForce::ImmediateOrCancel));
+        assert!(matches!(
+            event,
+            OrderEvent::PartiallyFilledCanceled {
+                filled_qty: Qty(3),
+                canceled_qty: Qty(1),
+                ..
             }
+        ));
+        assert_eq!(ob.best_bid(), None);
+        assert_eq!(ob.best_ask(), None);
+    }
+
+    #[test]
+    fn expiry_sweeps_gtd_and_day_orders() {
+        let mut ob = OrderBook::new(16);
+        let now = Utc::now();
+        let (gtd, day, gtc) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        for (id, tif) in [
+            (gtd, TimeInForce::GoodTillDate(now)),
+            (day, TimeInForce::Day),
+            (gtc, TimeInForce::GoodTillCancel),
+        ] {
+            ob.execute(limit(id, Side::Bid, 1, 100).tif(tif));
         }
 
-        for (i, (bid_price, queue)) in self.bids.iter().enumerate() {
-            if i > levels {
-                break;
+        assert!(ob.expire(now - chrono::Duration::seconds(1)).is_empty());
+        assert_eq!(
+            ob.expire(now),
+            vec![OrderEvent::Expired {
+                id: gtd,
+                qty: Qty(1)
+            }]
+        );
+        assert_eq!(
+            ob.expire_day_orders(),
+            vec![OrderEvent::Expired {
+                id: day,
+                qty: Qty(1)
+            }]
+        );
+        assert_eq!(ob.depth(1).bids[0].qty, Qty(1));
+
+        // orders leave the sweeps when they are canceled, modified or filled
+        for _ in 0..1000 {
+            let (day, gtd) = (Uuid::new_v4(), Uuid::new_v4());
+            ob.execute(limit(day, Side::Bid, 2, 90).tif(TimeInForce::Day));
+            ob.execute(limit(gtd, Side::Ask, 1, 110).tif(TimeInForce::GoodTillDate(now)));
+            ob.execute(OrderType::Modify {
+                id: day,
+                new_price: Price(91),
+                new_qty: Qty(2),
+            });
+            ob.execute(OrderType::Cancel { id: day });
+            ob.execute(limit(Uuid::new_v4(), Side::Bid, 1, 110));
+        }
+        assert!(ob.day_orders.is_empty());
+        assert!(ob.expiries.is_empty());
+    }
+
+    #[test]
+    fn post_only_orders_never_take_liquidity() {
+        let mut ob = OrderBook::new(16);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 1, 100));
+        let post_only = |id| limit(id, Side::Bid, 1, 101).post_only();
+
+        let id = Uuid::new_v4();
+        assert_eq!(
+            ob.execute(post_only(id)),
+            OrderEvent::Rejected {
+                id,
+                reason: RejectReason::WouldCross
             }
-            let mut qty = 0.0;
-            for idx in queue {
-                qty += self.arena[*idx].qty;
+        );
+
+        ob.set_post_only_mode(PostOnlyMode::Reprice);
+        let id = Uuid::new_v4();
+        assert_eq!(
+            ob.execute(post_only(id)),
+            OrderEvent::Repriced {
+                id,
+                price: Price(99)
             }
-            if qty > 0.0 {
-                bids.push(BookLevel {
-                    price: **bid_price,
-                    qty,
-                });
+        );
+        assert_eq!(ob.best_bid(), Some(Price(99)));
+        assert_eq!(ob.best_ask(), Some(Price(100)));
+
+        // modifying a quote into the spread cannot take liquidity either
+        let modify = |new_price| OrderType::Modify {
+            id,
+            new_price: Price(new_price),
+            new_qty: Qty(1),
+        };
+        assert!(matches!(
+            ob.execute(modify(101)),
+            OrderEvent::Modified {
+                price: Price(99),
+                ref fills,
+                ..
+            } if fills.is_empty()
+        ));
+        ob.set_post_only_mode(PostOnlyMode::Reject);
+        assert_eq!(
+            ob.execute(modify(100)),
+            OrderEvent::Rejected {
+                id,
+                reason: RejectReason::WouldCross
             }
+        );
+        assert_eq!(ob.l3_snapshot().bids[0].orders[0].id, id);
+        assert_eq!(ob.best_ask(), Some(Price(100)));
+
+        let mut ob = OrderBook::new(16);
+        ob.set_post_only_mode(PostOnlyMode::Reprice);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 1, 1));
+        let id = Uuid::new_v4();
+        assert_eq!(
+            ob.execute(post_only(id)),
+            OrderEvent::Rejected {
+                id,
+                reason: RejectReason::InvalidPrice
+            }
+        );
+        assert_eq!(ob.best_bid(), None);
+    }
+
+    #[test]
+    fn stops_trigger_on_last_trade_in_the_same_call() {
+        let mut ob = OrderBook::new(16);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 1, 100));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 5, 101));
+        let stop = Uuid::new_v4();
+        ob.execute(OrderType::Stop {
+            id: stop,
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(2),
+            stop_price: Price(101),
+        });
+
+        ob.execute(OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(1),
+            protection: None,
+            min_qty: None,
+        });
+        assert!(ob.cascade().is_empty());
+
+        ob.execute(OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(1),
+            protection: None,
+            min_qty: None,
+        });
+        assert_eq!(ob.cascade()[0], OrderEvent::Triggered { id: stop });
+        assert!(matches!(
+            ob.cascade()[1],
+            OrderEvent::Filled {
+                id,
+                filled_qty: Qty(2),
+                ..
+            } if id == stop
+        ));
+        assert_eq!(ob.last_trade().unwrap().price, Price(101));
+        assert_eq!(ob.traded_volume(), Qty(4));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(2));
+    }
+
+    #[test]
+    fn iceberg_refreshes_at_the_back_of_the_queue() {
+        let mut ob = OrderBook::new(16);
+        let open = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+        ob.set_time(open);
+        let (iceberg, plain) = (Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(iceberg, Side::Ask, 10, 100).display_qty(2));
+        ob.execute(limit(plain, Side::Ask, 3, 100));
+        ob.set_time(open + chrono::Duration::seconds(1));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(5));
+
+        let fills = ob
+            .execute(OrderType::Market {
+                id: Uuid::new_v4(),
+                owner: 0,
+                side: Side::Bid,
+                qty: Qty(3),
+                protection: None,
+                min_qty: None,
+            })
+            .fills()
+            .to_vec();
+        assert_eq!(
+            fills.iter().map(|f| (f.order_2, f.qty)).collect::<Vec<_>>(),
+            vec![(iceberg, Qty(2)), (plain, Qty(1))]
+        );
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(4));
+        // the refreshed slice is stamped when it goes back in the queue
+        let queue: Vec<(Uuid, DateTime<Utc>)> = ob.l3_snapshot().asks[0]
+            .orders
+            .iter()
+            .map(|order| (order.id, order.timestamp))
+            .collect();
+        assert_eq!(
+            queue,
+            vec![
+                (plain, open),
+                (iceberg, open + chrono::Duration::seconds(1))
+            ]
+        );
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn market_orders_stop_at_their_protection_band() {
+        let market = |qty, protection| OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(qty),
+            protection,
+            min_qty: None,
+        };
+        let mut ob = OrderBook::new(16);
+        for price in [1000, 1005, 1010, 1200] {
+            ob.execute(limit(Uuid::new_v4(), Side::Ask, 2, price));
         }
-        BookDepth { levels, asks, bids }
+
+        match ob.execute(market(5, Some(PriceBand::Ticks(5)))) {
+            OrderEvent::Protected {
+                filled_qty,
+                canceled_qty,
+                limit_price,
+                fills,
+                ..
+            } => {
+                assert_eq!((filled_qty, canceled_qty), (Qty(4), Qty(1)));
+                assert_eq!(limit_price, Price(1005));
+                assert_eq!(fills.last().unwrap().price, Price(1005));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // 1% of the touch at 1010 is 10 ticks, the book default applies without a band
+        ob.set_market_protection(Some(PriceBand::BasisPoints(100)));
+        assert!(matches!(
+            ob.execute(market(3, None)),
+            OrderEvent::Protected {
+                filled_qty: Qty(2),
+                limit_price: Price(1020),
+                ..
+            }
+        ));
+        assert!(matches!(
+            ob.execute(market(3, Some(PriceBand::Ticks(500)))),
+            OrderEvent::PartiallyFilledCanceled {
+                filled_qty: Qty(2),
+                ..
+            }
+        ));
+        assert!(matches!(
+            ob.execute(market(1, Some(PriceBand::Ticks(0)))),
+            OrderEvent::Rejected {
+                reason: RejectReason::InvalidProtection,
+                ..
+            }
+        ));
+        assert_aggregates(&ob);
+
+        // an all-or-none order inside the band is passed by, nothing is left beyond it
+        let mut ob = OrderBook::new(16);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 10, 100).aon());
+        let id = Uuid::new_v4();
+        assert_eq!(
+            ob.execute(OrderType::Market {
+                id,
+                owner: 0,
+                side: Side::Bid,
+                qty: Qty(3),
+                protection: Some(PriceBand::Ticks(5)),
+                min_qty: None,
+            }),
+            OrderEvent::Unfilled { id }
+        );
+
+        // wide bands saturate instead of overflowing
+        assert_eq!(
+            PriceBand::BasisPoints(i64::MAX).limit(Side::Bid, Price::MAX),
+            Price(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn min_qty_orders_fill_enough_or_nothing() {
+        let mut ob = OrderBook::new(16);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 3, 100));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 2, 101));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 4, 110));
+
+        assert!(matches!(
+            ob.execute(limit(Uuid::new_v4(), Side::Bid, 10, 101).min_qty(6)),
+            OrderEvent::Killed { .. }
+        ));
+        assert!(matches!(
+            ob.execute(limit(Uuid::new_v4(), Side::Bid, 10, 101).min_qty(5)),
+            OrderEvent::PartiallyFilled {
+                filled_qty: Qty(5),
+                ..
+            }
+        ));
+        assert_eq!(ob.best_bid(), Some(Price(101)));
+
+        let market = |min_qty| OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(6),
+            protection: None,
+            min_qty: Some(Qty(min_qty)),
+        };
+        assert!(matches!(ob.execute(market(5)), OrderEvent::Killed { .. }));
+        assert!(matches!(
+            ob.execute(market(4)),
+            OrderEvent::PartiallyFilledCanceled {
+                filled_qty: Qty(4),
+                ..
+            }
+        ));
+        assert!(matches!(
+            ob.execute(limit(Uuid::new_v4(), Side::Bid, 2, 101).min_qty(3)),
+            OrderEvent::Rejected {
+                reason: RejectReason::InvalidMinQty,
+                ..
+            }
+        ));
+        assert_aggregates(&ob);
+
+        // liquidity of the same owner does not count when self-trade prevention would cancel
+        let mut ob = OrderBook::new(16);
+        ob.set_stp_mode(StpMode::CancelNewest);
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 1, 100));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 5, 100).owner(1));
+        let market = OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 1,
+            side: Side::Bid,
+            qty: Qty(6),
+            protection: None,
+            min_qty: Some(Qty(5)),
+        };
+        assert!(matches!(ob.execute(market), OrderEvent::Killed { .. }));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(6));
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn all_or_none_orders_are_passed_by_smaller_orders() {
+        let mut ob = OrderBook::new(16);
+        let (block, small, next) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(block, Side::Ask, 5, 100).aon());
+        ob.execute(limit(small, Side::Ask, 3, 100));
+        ob.execute(limit(next, Side::Ask, 4, 101));
+
+        // too small for the block, it trades behind it and at the next level
+        let event = ob.execute(limit(Uuid::new_v4(), Side::Bid, 4, 101));
+        let makers: Vec<(Uuid, Qty)> = event
+            .fills()
+            .iter()
+            .map(|fill| (fill.order_2, fill.qty))
+            .collect();
+        assert_eq!(makers, vec![(small, Qty(3)), (next, Qty(1))]);
+        let fok = limit(Uuid::new_v4(), Side::Bid, 4, 100).tif(TimeInForce::FillOrKill);
+        assert!(matches!(ob.execute(fok), OrderEvent::Killed { .. }));
+
+        let event = ob.execute(limit(Uuid::new_v4(), Side::Bid, 6, 100));
+        assert_eq!(event.fills().len(), 1);
+        assert_eq!(
+            (event.fills()[0].order_2, event.fills()[0].qty),
+            (block, Qty(5))
+        );
+
+        // an incoming all-or-none order that cannot fill completely rests without trading
+        let waiting = Uuid::new_v4();
+        assert_eq!(
+            ob.execute(limit(waiting, Side::Bid, 10, 101).aon()),
+            OrderEvent::Placed { id: waiting }
+        );
+        assert_eq!(
+            (ob.best_bid(), ob.best_ask()),
+            (Some(Price(101)), Some(Price(101)))
+        );
+        assert!(matches!(
+            ob.execute(
+                limit(Uuid::new_v4(), Side::Ask, 10, 102)
+                    .display_qty(2)
+                    .aon()
+            ),
+            OrderEvent::Rejected {
+                reason: RejectReason::InvalidDisplayQty,
+                ..
+            }
+        ));
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn self_trade_prevention_modes() {
+        let mut ob = OrderBook::new(16);
+        ob.set_stp_mode(StpMode::CancelOldest);
+        let (own, other, bid) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(own, Side::Ask, 5, 100).owner(1));
+        ob.execute(limit(other, Side::Ask, 5, 100).owner(2));
+        let event = ob.execute(limit(bid, Side::Bid, 7, 100).owner(1));
+        assert!(matches!(
+            event,
+            OrderEvent::PartiallyFilled {
+                filled_qty: Qty(5),
+                ..
+            }
+        ));
+        assert_eq!(
+            ob.cascade(),
+            &[OrderEvent::SelfTradePrevented {
+                id: own,
+                canceled_qty: Qty(5)
+            }]
+        );
+        assert_eq!(ob.depth(1).bids[0].qty, Qty(2));
+
+        let mut ob = OrderBook::new(16);
+        ob.set_stp_mode(StpMode::DecrementAndCancel);
+        let (ask, bid) = (Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(ask, Side::Ask, 5, 100).owner(1));
+        let event = ob.execute(limit(bid, Side::Bid, 3, 100).owner(1));
+        assert_eq!(event, OrderEvent::Unfilled { id: bid });
+        assert_eq!(ob.cascade().len(), 2);
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(2));
+        assert_eq!(ob.best_bid(), None);
+        assert_aggregates(&ob);
+
+        // a fill-or-kill order that would run into its own order is killed untouched
+        let mut ob = OrderBook::new(16);
+        ob.set_stp_mode(StpMode::CancelNewest);
+        let (other, own) = (Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(other, Side::Ask, 3, 100).owner(2));
+        ob.execute(limit(own, Side::Ask, 3, 100).owner(1));
+        let fok = Uuid::new_v4();
+        let fill_or_kill = limit(fok, Side::Bid, 6, 100)
+            .owner(1)
+            .tif(TimeInForce::FillOrKill);
+        assert_eq!(ob.execute(fill_or_kill), OrderEvent::Killed { id: fok });
+        assert!(ob.cascade().is_empty());
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(6));
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn pro_rata_splits_a_level() {
+        let mut ob = OrderBook::new(16);
+        ob.set_matching_policy(ProRata::default());
+        let (big, small) = (Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(small, Side::Ask, 10, 100));
+        ob.execute(limit(big, Side::Ask, 30, 100));
+
+        let event = ob.execute(OrderType::Market {
+            id: Uuid::new_v4(),
+            owner: 0,
+            side: Side::Bid,
+            qty: Qty(8),
+            protection: None,
+            min_qty: None,
+        });
+        assert_eq!(
+            event
+                .fills()
+                .iter()
+                .map(|f| (f.order_2, f.qty))
+                .collect::<Vec<_>>(),
+            vec![(small, Qty(2)), (big, Qty(6))]
+        );
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(32));
+    }
+
+    #[test]
+    fn auction_uncrosses_at_a_single_price() {
+        let mut ob = OrderBook::new(16);
+        ob.begin_auction();
+        let (bid1, bid2, ask1, ask2) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        ob.execute(limit(bid1, Side::Bid, 10, 102));
+        ob.execute(limit(bid2, Side::Bid, 10, 100));
+        ob.execute(limit(ask1, Side::Ask, 5, 99));
+        assert_eq!(
+            ob.execute(limit(ask2, Side::Ask, 10, 101)),
+            OrderEvent::Placed { id: ask2 }
+        );
+        assert_eq!(ob.best_bid(), Some(Price(102)));
+        assert_eq!(ob.best_ask(), Some(Price(99)));
+
+        let eq = ob.equilibrium(None).unwrap();
+        assert_eq!((eq.price, eq.volume), (Price(101), Qty(10)));
+
+        let events = ob.uncross(None);
+        assert!(!ob.in_auction());
+        assert!(events
+            .iter()
+            .flat_map(|event| event.fills())
+            .all(|fill| fill.price == Price(101)));
+        assert!(matches!(events[0], OrderEvent::Filled { id, .. } if id == bid1));
+        assert_eq!(ob.last_trade().unwrap().price, Price(101));
+        assert_eq!(ob.traded_volume(), Qty(10));
+        assert_eq!(ob.best_bid(), Some(Price(100)));
+        assert_eq!(ob.best_ask(), Some(Price(101)));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(5));
+        assert_aggregates(&ob);
+
+        // an all-or-none order the auction cannot fill completely is left out of it
+        let mut ob = OrderBook::new(16);
+        ob.begin_auction();
+        let (aon, bid, ask) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(aon, Side::Bid, 10, 101).aon());
+        ob.execute(limit(ask, Side::Ask, 3, 100));
+        assert_eq!(ob.equilibrium(None), None);
+        ob.execute(limit(bid, Side::Bid, 2, 100));
+        let eq = ob.equilibrium(None).unwrap();
+        assert_eq!((eq.price, eq.volume), (Price(100), Qty(2)));
+
+        let events = ob.uncross(None);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], OrderEvent::Filled { id, .. } if id == bid));
+        assert_eq!(ob.depth(1).bids[0].qty, Qty(10));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(1));
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn rejects_invalid_orders_before_they_touch_the_book() {
+        let mut ob = OrderBook::new(16);
+        let reject = |ob: &mut OrderBook, order: OrderType| match ob.execute(order) {
+            OrderEvent::Rejected { reason, .. } => Some(reason),
+            _ => None,
+        };
+        let id = Uuid::new_v4();
+        ob.execute(limit(id, Side::Ask, 10, 100));
+
+        assert_eq!(
+            reject(&mut ob, limit(Uuid::new_v4(), Side::Bid, -1, 100)),
+            Some(RejectReason::InvalidQty)
+        );
+        assert_eq!(
+            reject(
+                &mut ob,
+                limit(Uuid::new_v4(), Side::Bid, Qty::MAX.0 + 1, 100)
+            ),
+            Some(RejectReason::InvalidQty)
+        );
+        assert_eq!(
+            reject(&mut ob, limit(Uuid::new_v4(), Side::Bid, 1, 0)),
+            Some(RejectReason::InvalidPrice)
+        );
+        assert_eq!(
+            reject(
+                &mut ob,
+                limit(Uuid::new_v4(), Side::Bid, 1, Price::MAX.0 + 1)
+            ),
+            Some(RejectReason::InvalidPrice)
+        );
+        assert_eq!(
+            reject(&mut ob, limit(id, Side::Bid, 1, 100)),
+            Some(RejectReason::DuplicateOrderId)
+        );
+        assert_eq!(
+            reject(
+                &mut ob,
+                limit(Uuid::new_v4(), Side::Ask, 10, 101).display_qty(0)
+            ),
+            Some(RejectReason::InvalidDisplayQty)
+        );
+        assert_eq!(
+            reject(&mut ob, OrderType::Cancel { id: Uuid::new_v4() }),
+            Some(RejectReason::UnknownOrder)
+        );
+        assert_eq!(
+            reject(
+                &mut ob,
+                OrderType::Modify {
+                    id,
+                    new_price: Price(-5),
+                    new_qty: Qty(5),
+                }
+            ),
+            Some(RejectReason::InvalidPrice)
+        );
+        assert_eq!(ob.best_ask(), Some(Price(100)));
+        assert_eq!(ob.best_bid(), None);
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(10));
+
+        assert_eq!(
+            ob.execute(OrderType::Cancel { id }),
+            OrderEvent::Canceled { id }
+        );
+        assert_eq!(
+            reject(&mut ob, OrderType::Cancel { id }),
+            Some(RejectReason::UnknownOrder)
+        );
+    }
+
+    #[test]
+    fn capacity_bounds_resting_orders() {
+        let mut ob = OrderBook::new(2);
+        ob.set_max_orders(Some(2));
+        let (ask1, ask2, ask3) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        ob.execute(limit(ask1, Side::Ask, 5, 100));
+        ob.execute(limit(ask2, Side::Ask, 5, 101));
+        assert_eq!(
+            ob.execute(limit(ask3, Side::Ask, 5, 102)),
+            OrderEvent::Rejected {
+                id: ask3,
+                reason: RejectReason::ArenaFull
+            }
+        );
+
+        // the capacity is only checked for what is left to rest
+        let bid = Uuid::new_v4();
+        assert!(matches!(
+            ob.execute(limit(bid, Side::Bid, 3, 100)),
+            OrderEvent::Filled { .. }
+        ));
+        assert_eq!(ob.depth(1).asks[0].qty, Qty(2));
+
+        // immediate orders never take a slot
+        let ioc = limit(Uuid::new_v4(), Side::Bid, 5, 100).tif(TimeInForce::ImmediateOrCancel);
+        assert!(matches!(
+            ob.execute(ioc),
+            OrderEvent::PartiallyFilledCanceled { .. }
+        ));
+        assert_eq!(ob.open_orders(), 1);
+
+        for _ in 0..100 {
+            let id = Uuid::new_v4();
+            ob.execute(limit(id, Side::Bid, 1, 90));
+            ob.execute(OrderType::Cancel { id });
+        }
+        assert_eq!(ob.open_orders(), 1);
+        assert_eq!(
+            ob.execute(limit(ask3, Side::Ask, 5, 102)),
+            OrderEvent::Placed { id: ask3 }
+        );
+    }
+
+    /// Level totals have to match the orders linked to each level
+    fn assert_aggregates(ob: &OrderBook) {
+        for queue in ob.asks.values().chain(ob.bids.values()) {
+            let orders: Vec<usize> = ob.arena.iter(queue).collect();
+            let qty = orders
+                .iter()
+                .fold(Qty::ZERO, |acc, idx| acc + ob.arena[*idx].qty);
+            let open_qty = orders
+                .iter()
+                .fold(Qty::ZERO, |acc, idx| acc + ob.arena[*idx].open_qty());
+            assert_eq!(
+                (queue.qty(), queue.open_qty(), queue.len()),
+                (qty, open_qty, orders.len())
+            );
+        }
+    }
+
+    #[test]
+    fn depth_is_kept_per_level() {
+        let mut ob = OrderBook::new(16);
+        for (qty, price) in [(5, 99), (3, 98), (2, 98), (4, 97)] {
+            ob.execute(limit(Uuid::new_v4(), Side::Bid, qty, price));
+        }
+        let iceberg = Uuid::new_v4();
+        ob.execute(limit(iceberg, Side::Ask, 10, 101).display_qty(4));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 6, 102));
+
+        let depth = ob.depth(2);
+        let bids: Vec<(Price, Qty, usize)> = depth
+            .bids
+            .iter()
+            .map(|level| (level.price, level.qty, level.orders))
+            .collect();
+        assert_eq!(bids, vec![(Price(99), Qty(5), 1), (Price(98), Qty(5), 2)]);
+        assert_eq!(depth.asks[0].qty, Qty(4));
+        assert_eq!(ob.level(Side::Bid, Price(97)).unwrap().qty, Qty(4));
+        assert_eq!(ob.level(Side::Bid, Price(96)), None);
+
+        let cumulative = ob.cumulative_depth(Side::Bid, 10);
+        assert_eq!(cumulative.len(), 3);
+        assert_eq!(
+            cumulative[2],
+            BookLevel {
+                price: Price(97),
+                qty: Qty(14),
+                orders: 4
+            }
+        );
+        assert_aggregates(&ob);
+
+        // fills refresh the iceberg and empty a level, a modify shrinks the iceberg
+        ob.execute(limit(Uuid::new_v4(), Side::Bid, 5, 101));
+        assert_eq!(
+            ob.level(Side::Ask, Price(101)),
+            Some(BookLevel {
+                price: Price(101),
+                qty: Qty(3),
+                orders: 1
+            })
+        );
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 6, 98));
+        assert_eq!(ob.level(Side::Bid, Price(99)), None);
+        assert_eq!(ob.level(Side::Bid, Price(98)).unwrap().qty, Qty(4));
+        ob.execute(OrderType::Modify {
+            id: iceberg,
+            new_price: Price(101),
+            new_qty: Qty(2),
+        });
+        assert_eq!(ob.level(Side::Ask, Price(101)).unwrap().qty, Qty(2));
+        assert_aggregates(&ob);
+    }
+
+    #[test]
+    fn fills_are_printed_on_the_tape() {
+        let mut ob = OrderBook::new(16);
+        ob.set_tape_capacity(2);
+        let now = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+        ob.set_time(now);
+        let makers: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (maker, price) in makers.iter().zip([100, 101, 102]) {
+            ob.execute(limit(*maker, Side::Ask, 2, price));
+        }
+        let taker = Uuid::new_v4();
+        ob.execute(limit(taker, Side::Bid, 5, 102));
+
+        let trades: Vec<Trade> = ob.recent_trades().copied().collect();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(
+            trades[1],
+            Trade {
+                id: 3,
+                price: Price(102),
+                qty: Qty(1),
+                aggressor: Side::Bid,
+                taker,
+                maker: makers[2],
+                timestamp: now,
+            }
+        );
+        let stats = ob.trade_stats();
+        assert_eq!(
+            (stats.low, stats.high),
+            (Some(Price(100)), Some(Price(102)))
+        );
+        assert_eq!((stats.volume, stats.count), (Qty(5), 3));
+        assert_eq!(stats.vwap(), Some(100.8));
+
+        ob.reset_trade_stats();
+        assert_eq!(ob.traded_volume(), Qty::ZERO);
+        assert_eq!(ob.last_trade(), Some(trades[1]));
+    }
+
+    #[test]
+    fn fills_are_charged_maker_and_taker_fees() {
+        let mut ob = OrderBook::new(16);
+        ob.set_fee_schedule(FeeSchedule::flat(
+            FeeRate::PerLot(-0.5),
+            FeeRate::BasisPoints(10.0),
+        ));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 2, 1000).owner(1));
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 2, 1010).owner(1));
+        let event = ob.execute(limit(Uuid::new_v4(), Side::Bid, 3, 1010).owner(2));
+
+        let fees: Vec<(u64, u64, f64, f64)> = event
+            .fills()
+            .iter()
+            .map(|fill| (fill.owner_1, fill.owner_2, fill.fee_1, fill.fee_2))
+            .collect();
+        assert_eq!(fees, vec![(2, 1, 2.0, -1.0), (2, 1, 1.01, -0.5)]);
+        assert_eq!((ob.trader_volume(1), ob.trader_volume(2)), (Qty(3), Qty(3)));
+
+        // auction fills are charged too
+        ob.begin_auction();
+        ob.execute(limit(Uuid::new_v4(), Side::Ask, 1, 1000).owner(3));
+        ob.execute(limit(Uuid::new_v4(), Side::Bid, 1, 1000).owner(4));
+        let events = ob.uncross(None);
+        assert_eq!(events[0].fills()[0].fee_1, 1.0);
+        assert_eq!((ob.trader_volume(3), ob.trader_volume(4)), (Qty(1), Qty(1)));
+    }
+
+    #[test]
+    fn l3_views_list_orders_in_priority() {
+        let mut ob = OrderBook::new(16);
+        let at = |s| Utc.with_ymd_and_hms(2022, 10, 3, 9, 0, s).unwrap();
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (i, (id, price)) in ids.iter().zip([100, 100, 101]).enumerate() {
+            ob.set_time(at(i as u32));
+            ob.execute(limit(*id, Side::Bid, 5, price));
+        }
+        // a smaller quantity keeps the place in the queue and the entry time
+        ob.execute(OrderType::Modify {
+            id: ids[0],
+            new_price: Price(100),
+            new_qty: Qty(2),
+        });
+
+        let level: Vec<(Uuid, Qty, usize)> = ob
+            .orders_at(Side::Bid, Price(100))
+            .map(|order| (order.id, order.qty, order.position))
+            .collect();
+        assert_eq!(level, vec![(ids[0], Qty(2), 0), (ids[1], Qty(5), 1)]);
+        let side: Vec<Uuid> = ob.orders(Side::Bid).map(|order| order.id).collect();
+        assert_eq!(side, vec![ids[2], ids[0], ids[1]]);
+        assert_eq!(ob.orders(Side::Ask).count(), 0);
+
+        ob.set_time(at(10));
+        let snapshot = ob.l3_snapshot();
+        assert_eq!(snapshot.timestamp, at(10));
+        assert!(snapshot.asks.is_empty());
+        assert_eq!(snapshot.bids[0].price, Price(101));
+        assert_eq!(snapshot.bids[1].orders[0].timestamp, at(0));
+        assert_eq!(snapshot.bids[1].orders[1].timestamp, at(1));
     }
 }
@@ -0,0 +1,319 @@
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::matching_engine::models::{OrderEvent, OrderType, RejectReason};
+use crate::matching_engine::orderbook::OrderBook;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradingPhase {
+    /// Opening auction, orders accumulate without matching
+    PreOpen,
+    Continuous,
+    /// Closing auction, orders accumulate without matching
+    ClosingAuction,
+    /// Only cancels are accepted
+    Halted,
+    /// Only cancels are accepted
+    Closed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionCommand {
+    /// Closed to pre-open, starts new trade statistics
+    StartPreOpen,
+    /// Pre-open to continuous, runs the opening uncross
+    Open,
+    /// Continuous to closing auction
+    StartClosingAuction,
+    /// Continuous, closing auction or halted to closed. Runs the closing uncross when coming
+    /// from the closing auction and expires day orders.
+    Close,
+    /// Any open phase to halted
+    Halt,
+    /// Halted back to the phase the book was halted in
+    Resume,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub phase: TradingPhase,
+    pub command: SessionCommand,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not allowed in {:?}", self.command, self.phase)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+/// Daily phase times, in UTC
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub pre_open: NaiveTime,
+    pub open: NaiveTime,
+    pub closing_auction: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl Schedule {
+    /// Phase the book should be in at `time`, ignoring halts
+    pub fn phase_at(&self, time: NaiveTime) -> TradingPhase {
+        if time < self.pre_open || time >= self.close {
+            TradingPhase::Closed
+        } else if time < self.open {
+            TradingPhase::PreOpen
+        } else if time < self.closing_auction {
+            TradingPhase::Continuous
+        } else {
+            TradingPhase::ClosingAuction
+        }
+    }
+}
+
+/// Order book driven through the phases of a trading day, either by explicit commands or by a
+/// simulated clock following a `Schedule`
+#[derive(Debug)]
+pub struct TradingSession {
+    book: OrderBook,
+    phase: TradingPhase,
+    halted_in: Option<TradingPhase>,
+    schedule: Option<Schedule>,
+}
+
+impl TradingSession {
+    /// Session starting closed
+    pub fn new(book: OrderBook) -> Self {
+        Self {
+            book,
+            phase: TradingPhase::Closed,
+            halted_in: None,
+            schedule: None,
+        }
+    }
+
+    pub fn with_schedule(book: OrderBook, schedule: Schedule) -> Self {
+        Self {
+            schedule: Some(schedule),
+            ..Self::new(book)
+        }
+    }
+
+    #[inline(always)]
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    #[inline(always)]
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    #[inline(always)]
+    pub fn book_mut(&mut self) -> &mut OrderBook {
+        &mut self.book
+    }
+
+    /// Sends an order to the book if the current phase accepts it
+    pub fn execute(&mut self, order: OrderType) -> OrderEvent {
+        let reason = match (self.phase, order) {
+            (_, OrderType::Cancel { .. }) => None,
+            (TradingPhase::Halted, _) => Some(RejectReason::TradingHalted),
+            (TradingPhase::Closed, _) => Some(RejectReason::MarketClosed),
+            _ => None,
+        };
+        match reason {
+            Some(reason) => OrderEvent::Rejected {
+                id: order.id(),
+                reason,
+            },
+            None => self.book.execute(order),
+        }
+    }
+
+    /// Moves to the next phase, returns the events of the uncross and expiries it runs
+    pub fn command(
+        &mut self,
+        command: SessionCommand,
+    ) -> Result<Vec<OrderEvent>, InvalidTransition> {
+        let mut events = Vec::new();
+        let next = match (self.phase, command) {
+            (TradingPhase::Closed, SessionCommand::StartPreOpen) => {
+                self.book.reset_trade_stats();
+                self.book.begin_auction();
+                TradingPhase::PreOpen
+            }
+            (TradingPhase::PreOpen, SessionCommand::Open) => {
+                events.extend(self.book.uncross(None));
+                TradingPhase::Continuous
+            }
+            (TradingPhase::Continuous, SessionCommand::StartClosingAuction) => {
+                self.book.begin_auction();
+                TradingPhase::ClosingAuction
+            }
+            (TradingPhase::ClosingAuction, SessionCommand::Close) => {
+                events.extend(self.book.uncross(None));
+                events.extend(self.book.expire_day_orders());
+                TradingPhase::Closed
+            }
+            (TradingPhase::Continuous, SessionCommand::Close) => {
+                events.extend(self.book.expire_day_orders());
+                TradingPhase::Closed
+            }
+            (TradingPhase::Halted, SessionCommand::Close) => {
+                // an auction halted before its uncross is closed like the phase it halted,
+                // the opening one without trading since the market never opened
+                match self.halted_in.take() {
+                    Some(TradingPhase::ClosingAuction) => events.extend(self.book.uncross(None)),
+                    Some(TradingPhase::PreOpen) => self.book.end_auction(),
+                    _ => {}
+                }
+                events.extend(self.book.expire_day_orders());
+                TradingPhase::Closed
+            }
+            (
+                TradingPhase::PreOpen | TradingPhase::Continuous | TradingPhase::ClosingAuction,
+                SessionCommand::Halt,
+            ) => {
+                self.halted_in = Some(self.phase);
+                TradingPhase::Halted
+            }
+            (TradingPhase::Halted, SessionCommand::Resume) => match self.halted_in.take() {
+                Some(phase) => phase,
+                None => TradingPhase::Closed,
+            },
+            (phase, command) => return Err(InvalidTransition { phase, command }),
+        };
+        self.phase = next;
+        Ok(events)
+    }
+
+    /// Advances the simulated clock: moves the book clock, expires good-till-date orders and,
+    /// when the session has a schedule, runs every transition needed to reach the scheduled
+    /// phase. A halted session stays halted until it is resumed.
+    pub fn advance_to(&mut self, now: DateTime<Utc>) -> Vec<OrderEvent> {
+        self.book.set_time(now);
+        let mut events = self.book.expire(now);
+        let target = match self.schedule {
+            Some(schedule) => schedule.phase_at(now.time()),
+            None => return events,
+        };
+        while self.phase != target && self.phase != TradingPhase::Halted {
+            let command = match self.phase {
+                TradingPhase::Closed => SessionCommand::StartPreOpen,
+                TradingPhase::PreOpen => SessionCommand::Open,
+                TradingPhase::Continuous => SessionCommand::StartClosingAuction,
+                TradingPhase::ClosingAuction | TradingPhase::Halted => SessionCommand::Close,
+            };
+            match self.command(command) {
+                Ok(transition_events) => events.extend(transition_events),
+                Err(_) => break,
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::{Qty, Side, TimeInForce};
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    /// Day order of 10 lots
+    fn day(side: Side, price: i64) -> OrderType {
+        limit(Uuid::new_v4(), side, 10, price).tif(TimeInForce::Day)
+    }
+
+    #[test]
+    fn runs_a_trading_day_from_the_clock() {
+        let at = |h, m| Utc.with_ymd_and_hms(2022, 10, 3, h, m, 0).unwrap();
+        let schedule = Schedule {
+            pre_open: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            open: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            closing_auction: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(16, 30, 0).unwrap(),
+        };
+        let mut session = TradingSession::with_schedule(OrderBook::new(16), schedule);
+
+        assert!(matches!(
+            session.execute(day(Side::Bid, 101)),
+            OrderEvent::Rejected {
+                reason: RejectReason::MarketClosed,
+                ..
+            }
+        ));
+
+        session.advance_to(at(7, 30));
+        assert_eq!(session.phase(), TradingPhase::PreOpen);
+        session.execute(day(Side::Bid, 101));
+        session.execute(day(Side::Ask, 100));
+        session.execute(day(Side::Ask, 102));
+
+        let events = session.advance_to(at(8, 0));
+        assert_eq!(session.phase(), TradingPhase::Continuous);
+        assert_eq!(events.len(), 2);
+
+        session.command(SessionCommand::Halt).unwrap();
+        let order = day(Side::Bid, 102);
+        assert!(matches!(
+            session.execute(order),
+            OrderEvent::Rejected {
+                reason: RejectReason::TradingHalted,
+                ..
+            }
+        ));
+        session.advance_to(at(12, 0));
+        assert_eq!(session.phase(), TradingPhase::Halted);
+        session.command(SessionCommand::Resume).unwrap();
+
+        let events = session.advance_to(at(17, 0));
+        assert_eq!(session.phase(), TradingPhase::Closed);
+        assert_eq!(
+            events,
+            vec![OrderEvent::Expired {
+                id: events[0].id(),
+                qty: Qty(10)
+            }]
+        );
+        assert!(session.command(SessionCommand::Open).is_err());
+    }
+
+    #[test]
+    fn closing_a_halted_auction_ends_it() {
+        let mut session = TradingSession::new(OrderBook::new(16));
+        session.command(SessionCommand::StartPreOpen).unwrap();
+        session.execute(day(Side::Bid, 101));
+        session.execute(limit(Uuid::new_v4(), Side::Ask, 10, 100));
+        session.command(SessionCommand::Halt).unwrap();
+
+        // the market never opened, nothing trades and the crossing order is kept
+        let events = session.command(SessionCommand::Close).unwrap();
+        assert_eq!(session.phase(), TradingPhase::Closed);
+        assert!(!session.book().in_auction());
+        assert!(matches!(events[..], [OrderEvent::Expired { .. }]));
+        assert_eq!(session.book().open_orders(), 1);
+
+        session.command(SessionCommand::StartPreOpen).unwrap();
+        session.command(SessionCommand::Open).unwrap();
+        session
+            .command(SessionCommand::StartClosingAuction)
+            .unwrap();
+        session.execute(day(Side::Bid, 101));
+        session.command(SessionCommand::Halt).unwrap();
+
+        // a halted closing auction still uncrosses
+        let events = session.command(SessionCommand::Close).unwrap();
+        assert_eq!(session.phase(), TradingPhase::Closed);
+        assert!(!session.book().in_auction());
+        assert!(matches!(
+            events[..],
+            [OrderEvent::Filled { .. }, OrderEvent::Filled { .. }]
+        ));
+        assert_eq!(session.book().open_orders(), 0);
+    }
+}
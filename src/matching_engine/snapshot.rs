@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::matching_engine::clock::Clock;
+use crate::matching_engine::fees::FeeEngine;
+use crate::matching_engine::models::{LimitOrder, PostOnlyMode, PriceBand, StpMode};
+use crate::matching_engine::tape::TradeTape;
+use crate::matching_engine::triggers::StopOrder;
+
+/// Full state of a book, taken by `OrderBook::snapshot` and loaded by `OrderBook::restore`.
+/// Levels and best prices follow from the resting orders. The matching policy is not part of
+/// the state, a restored book keeps its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    /// Resting asks from the best price, in time priority inside a level
+    pub asks: Vec<LimitOrder>,
+    /// Resting bids from the best price, in time priority inside a level
+    pub bids: Vec<LimitOrder>,
+    /// Stop orders waiting for their trigger, in arrival order at each stop price
+    pub stops: Vec<StopOrder>,
+    pub expiries: BTreeMap<DateTime<Utc>, Vec<Uuid>>,
+    pub day_orders: Vec<Uuid>,
+    pub post_only_mode: PostOnlyMode,
+    pub stp_mode: StpMode,
+    pub market_protection: Option<PriceBand>,
+    pub auction: bool,
+    pub clock: Clock,
+    pub max_orders: Option<usize>,
+    pub market_data: bool,
+    /// Sequence number of the last market data message, pending messages are not kept
+    pub market_data_seq: u64,
+    pub tape: TradeTape,
+    /// Fee schedule and the volumes that set the tier of each owner
+    pub fees: FeeEngine,
+}
+
+impl BookSnapshot {
+    /// Compact binary encoding
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+
+    /// Human-readable encoding, to inspect a book or write a scenario by hand
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::{OrderType, Price, Qty, Side, TimeInForce};
+    use crate::matching_engine::orderbook::OrderBook;
+    use crate::matching_engine::test_support::{limit, LimitBuilder};
+    use chrono::{Duration, TimeZone};
+
+    fn book() -> OrderBook {
+        let now = Utc.with_ymd_and_hms(2022, 10, 3, 9, 30, 0).unwrap();
+        let mut book = OrderBook::new(16);
+        book.set_time(now);
+        book.set_stp_mode(StpMode::CancelOldest);
+        book.set_market_data(true);
+        let gtd = TimeInForce::GoodTillDate(now + Duration::hours(1));
+        for order in [
+            limit(Uuid::new_v4(), Side::Ask, 10, 101)
+                .owner(1)
+                .display_qty(2),
+            limit(Uuid::new_v4(), Side::Ask, 5, 101).owner(1).tif(gtd),
+            limit(Uuid::new_v4(), Side::Ask, 4, 103)
+                .owner(1)
+                .tif(TimeInForce::Day),
+            limit(Uuid::new_v4(), Side::Bid, 6, 99).owner(2),
+            limit(Uuid::new_v4(), Side::Bid, 3, 98)
+                .owner(2)
+                .tif(TimeInForce::Day),
+            limit(Uuid::new_v4(), Side::Bid, 3, 101)
+                .owner(2)
+                .tif(TimeInForce::ImmediateOrCancel),
+            OrderType::Stop {
+                id: Uuid::new_v4(),
+                owner: 2,
+                side: Side::Bid,
+                qty: Qty(2),
+                stop_price: Price(102),
+            },
+        ] {
+            book.execute(order);
+        }
+        book
+    }
+
+    #[test]
+    fn restored_book_behaves_like_the_original() {
+        let mut original = book();
+        let snapshot = original.snapshot();
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(BookSnapshot::from_json(&json).unwrap(), snapshot);
+        let bytes = snapshot.to_bytes().unwrap();
+        assert_eq!(BookSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+
+        let mut restored = OrderBook::new(16);
+        restored.restore(BookSnapshot::from_json(&json).unwrap());
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.l3_snapshot(), original.l3_snapshot());
+        assert_eq!(restored.depth(10), original.depth(10));
+        assert_eq!(
+            (restored.best_bid(), restored.best_ask()),
+            (Some(Price(99)), Some(Price(101)))
+        );
+        assert_eq!(restored.trade_stats(), original.trade_stats());
+
+        // pending market data is dropped, numbering goes on
+        original.drain_market_data();
+        let sweep = limit(Uuid::new_v4(), Side::Bid, 12, 103).owner(2);
+        assert_eq!(restored.execute(sweep), original.execute(sweep));
+        assert_eq!(restored.cascade(), original.cascade());
+        assert_eq!(restored.drain_market_data(), original.drain_market_data());
+        let at = Utc.with_ymd_and_hms(2022, 10, 3, 11, 0, 0).unwrap();
+        assert_eq!(restored.expire(at), original.expire(at));
+        assert_eq!(restored.expire_day_orders(), original.expire_day_orders());
+        assert_eq!(restored.snapshot(), original.snapshot());
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::matching_engine::models::{FillMetadata, Price, Qty, Trade};
+
+/// Running statistics of the trades since the start of the session
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeStats {
+    pub last: Option<Price>,
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    pub volume: Qty,
+    pub count: u64,
+    /// Sum of price times quantity, in ticks times lots
+    pub notional: i128,
+}
+
+impl TradeStats {
+    /// Volume weighted average price, in ticks
+    pub fn vwap(&self) -> Option<f64> {
+        match self.volume.is_zero() {
+            true => None,
+            false => Some(self.notional as f64 / self.volume.0 as f64),
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.last = Some(trade.price);
+        self.high = Some(self.high.map_or(trade.price, |high| high.max(trade.price)));
+        self.low = Some(self.low.map_or(trade.price, |low| low.min(trade.price)));
+        self.volume += trade.qty;
+        self.count += 1;
+        self.notional += trade.price.0 as i128 * trade.qty.0 as i128;
+    }
+}
+
+/// Trades of a book: ids, running statistics and the most recent trades
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeTape {
+    last_id: u64,
+    last_trade: Option<Trade>,
+    stats: TradeStats,
+    recent: VecDeque<Trade>,
+    capacity: usize,
+}
+
+impl TradeTape {
+    /// Tape keeping the last `capacity` trades
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            last_id: 0,
+            last_trade: None,
+            stats: TradeStats::default(),
+            recent: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Prints a fill, `order_1` being the taker
+    pub fn record(&mut self, fill: &FillMetadata, timestamp: DateTime<Utc>) -> Trade {
+        self.last_id += 1;
+        let trade = Trade {
+            id: self.last_id,
+            price: fill.price,
+            qty: fill.qty,
+            aggressor: fill.taker_side,
+            taker: fill.order_1,
+            maker: fill.order_2,
+            timestamp,
+        };
+        self.stats.add(&trade);
+        self.last_trade = Some(trade);
+        if self.capacity > 0 {
+            if self.recent.len() == self.capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(trade);
+        }
+        trade
+    }
+
+    #[inline(always)]
+    pub fn last_trade(&self) -> Option<Trade> {
+        self.last_trade
+    }
+
+    #[inline(always)]
+    pub fn stats(&self) -> TradeStats {
+        self.stats
+    }
+
+    /// Most recent trades, oldest first
+    pub fn recent(&self) -> impl DoubleEndedIterator<Item = &Trade> {
+        self.recent.iter()
+    }
+
+    /// Changes how many trades are kept, dropping the oldest ones if needed
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.recent.len() > capacity {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Starts new session statistics, trade ids and recent trades are kept
+    pub fn reset_stats(&mut self) {
+        self.stats = TradeStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching_engine::models::Side;
+    use crate::matching_engine::test_support::fill;
+
+    #[test]
+    fn keeps_stats_and_recent_trades() {
+        let mut tape = TradeTape::new(2);
+        let now = Utc::now();
+        for (qty, price) in [(1, 100), (3, 104), (4, 101)] {
+            tape.record(&fill(1, 2, Side::Bid, qty, price), now);
+        }
+        let stats = tape.stats();
+        assert_eq!(stats.last, Some(Price(101)));
+        assert_eq!(
+            (stats.high, stats.low),
+            (Some(Price(104)), Some(Price(100)))
+        );
+        assert_eq!((stats.volume, stats.count), (Qty(8), 3));
+        assert_eq!(stats.vwap(), Some(102.0));
+
+        let ids: Vec<u64> = tape.recent().map(|trade| trade.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(tape.last_trade().unwrap().qty, Qty(4));
+
+        tape.reset_stats();
+        assert_eq!(tape.stats().vwap(), None);
+        assert_eq!(tape.record(&fill(1, 2, Side::Bid, 1, 99), now).id, 4);
+    }
+}
@@ -0,0 +1,89 @@
+use uuid::Uuid;
+
+use crate::matching_engine::models::{FillMetadata, OrderType, Price, Qty, Side, TimeInForce};
+
+/// Good-till-cancel limit order of owner 0, see `LimitBuilder` for the other fields
+pub fn limit(id: Uuid, side: Side, qty: i64, price: i64) -> OrderType {
+    OrderType::Limit {
+        id,
+        owner: 0,
+        side,
+        qty: Qty(qty),
+        price: Price(price),
+        tif: TimeInForce::GoodTillCancel,
+        post_only: false,
+        display_qty: None,
+        min_qty: None,
+        all_or_none: false,
+    }
+}
+
+/// Sets the optional fields of a limit order built by `limit`
+pub trait LimitBuilder {
+    fn owner(self, owner: u64) -> Self;
+    fn tif(self, tif: TimeInForce) -> Self;
+    fn post_only(self) -> Self;
+    fn display_qty(self, qty: i64) -> Self;
+    fn min_qty(self, qty: i64) -> Self;
+    fn aon(self) -> Self;
+}
+
+impl LimitBuilder for OrderType {
+    fn owner(mut self, value: u64) -> Self {
+        if let OrderType::Limit { owner, .. } = &mut self {
+            *owner = value;
+        }
+        self
+    }
+
+    fn tif(mut self, value: TimeInForce) -> Self {
+        if let OrderType::Limit { tif, .. } = &mut self {
+            *tif = value;
+        }
+        self
+    }
+
+    fn post_only(mut self) -> Self {
+        if let OrderType::Limit { post_only, .. } = &mut self {
+            *post_only = true;
+        }
+        self
+    }
+
+    fn display_qty(mut self, qty: i64) -> Self {
+        if let OrderType::Limit { display_qty, .. } = &mut self {
+            *display_qty = Some(Qty(qty));
+        }
+        self
+    }
+
+    fn min_qty(mut self, qty: i64) -> Self {
+        if let OrderType::Limit { min_qty, .. } = &mut self {
+            *min_qty = Some(Qty(qty));
+        }
+        self
+    }
+
+    fn aon(mut self) -> Self {
+        if let OrderType::Limit { all_or_none, .. } = &mut self {
+            *all_or_none = true;
+        }
+        self
+    }
+}
+
+/// Fill of a `taker` order against a `maker` order, charged no fees
+pub fn fill(taker: u64, maker: u64, side: Side, qty: i64, price: i64) -> FillMetadata {
+    FillMetadata {
+        order_1: Uuid::new_v4(),
+        order_2: Uuid::new_v4(),
+        owner_1: taker,
+        owner_2: maker,
+        qty: Qty(qty),
+        price: Price(price),
+        taker_side: side,
+        total_fill: false,
+        fee_1: 0.0,
+        fee_2: 0.0,
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use crate::matching_engine::models::{OrderType, Price, Qty, Side, TimeInForce};
+
+/// Stop or stop-limit order waiting for its trigger price
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StopOrder {
+    pub id: Uuid,
+    pub owner: u64,
+    pub side: Side,
+    pub qty: Qty,
+    pub stop_price: Price,
+    /// Limit price and time in force of stop-limit orders, stop orders become market orders
+    pub limit: Option<(Price, TimeInForce)>,
+}
+
+impl StopOrder {
+    /// Order sent to matching once the stop is triggered
+    pub fn into_order(self) -> OrderType {
+        match self.limit {
+            None => OrderType::Market {
+                id: self.id,
+                owner: self.owner,
+                side: self.side,
+                qty: self.qty,
+                protection: None,
+                min_qty: None,
+            },
+            Some((price, tif)) => OrderType::Limit {
+                id: self.id,
+                owner: self.owner,
+                side: self.side,
+                qty: self.qty,
+                price,
+                tif,
+                post_only: false,
+                display_qty: None,
+                min_qty: None,
+                all_or_none: false,
+            },
+        }
+    }
+}
+
+/// Conditional orders keyed by stop price.
+/// Buy stops trigger when the last trade is at or above the stop price, sell stops when it is at
+/// or below. Orders with the same stop price trigger in arrival order.
+#[derive(Debug, Default)]
+pub struct TriggerBook {
+    buy_stops: BTreeMap<Price, Vec<StopOrder>>,
+    sell_stops: BTreeMap<Price, Vec<StopOrder>>,
+    index: HashMap<Uuid, (Side, Price)>,
+}
+
+impl TriggerBook {
+    pub fn insert(&mut self, order: StopOrder) {
+        let stops = match order.side {
+            Side::Bid => &mut self.buy_stops,
+            Side::Ask => &mut self.sell_stops,
+        };
+        stops.entry(order.stop_price).or_default().push(order);
+        self.index.insert(order.id, (order.side, order.stop_price));
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn cancel(&mut self, id: Uuid) -> bool {
+        let (side, stop_price) = match self.index.remove(&id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let stops = match side {
+            Side::Bid => &mut self.buy_stops,
+            Side::Ask => &mut self.sell_stops,
+        };
+        if let Some(queue) = stops.get_mut(&stop_price) {
+            queue.retain(|order| order.id != id);
+            if queue.is_empty() {
+                stops.remove(&stop_price);
+            }
+        }
+        true
+    }
+
+    /// Waiting buy stops then sell stops, by stop price and in arrival order at each price
+    pub fn orders(&self) -> impl Iterator<Item = &StopOrder> {
+        self.buy_stops
+            .values()
+            .chain(self.sell_stops.values())
+            .flatten()
+    }
+
+    /// Removes and returns the orders triggered by a trade at `last_price`, in the order the
+    /// price went through their stop prices
+    pub fn take_triggered(&mut self, last_price: Price) -> Vec<StopOrder> {
+        let mut triggered = Vec::new();
+        while let Some(entry) = self.buy_stops.first_entry() {
+            if *entry.key() > last_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+        while let Some(entry) = self.sell_stops.last_entry() {
+            if *entry.key() < last_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+        for order in &triggered {
+            self.index.remove(&order.id);
+        }
+        triggered
+    }
+}
@@ -167,7 +167,7 @@ impl OrderGenerator {
     ) -> Order {
         let key_id = rng.gen_range(0..limit_orders.keys().len());
         let (_key, order) = limit_orders.iter_mut().nth(key_id).unwrap();
-        let orders = &mut self.traders[trader_id as usize].orders;
+        let orders = &mut self.traders[trader_id].orders;
         orders.remove(&order.id);
         order.id = Uuid::new_v4();
         order.event = EventType::Cancel;
@@ -210,7 +210,7 @@ impl OrderGenerator {
     ) -> Order {
         let key_id = rng.gen_range(0..limit_orders.keys().len());
         let (key, order) = limit_orders.iter_mut().nth(key_id).unwrap();
-        let orders = &mut self.traders[trader_id as usize].orders;
+        let orders = &mut self.traders[trader_id].orders;
         // let mut updated_price = price;
         // let mut updated_qty = qty;
         let (update_price, update_qty) = match rng.gen_range(0..=1) {
@@ -233,14 +233,12 @@ impl OrderGenerator {
         orders.insert(*key, order.clone());
 
         order.id = Uuid::new_v4();
-        match update_price {
-            Some(price) => order.price = price,
-            None => {}
-        };
-        match update_qty {
-            Some(qty) => order.qty = qty,
-            None => {}
-        };
+        if let Some(price) = update_price {
+            order.price = price;
+        }
+        if let Some(qty) = update_qty {
+            order.qty = qty;
+        }
         order.event = EventType::Update;
         order.sequence = sequence;
         order.time = chrono::offset::Utc::now();
@@ -263,7 +261,7 @@ impl OrderGenerator {
             .into_values()
             .any(|order| order.kind == OrderKind::Limit);
 
-        if trader.orders.len() > 0 && has_limit_orders {
+        if !trader.orders.is_empty() && has_limit_orders {
             let mut limit_orders: HashMap<Uuid, Order> = trader
                 .orders
                 .into_iter()
@@ -293,7 +291,7 @@ impl OrderGenerator {
 
 #[derive(Debug, Clone)]
 pub struct Trader {
-    id: u64,
+    /// Open orders of the trader, whose id is its index in the generator
     orders: HashMap<Uuid, Order>,
 }
 
@@ -304,6 +302,8 @@ pub struct OrderSimulation {
 }
 
 impl OrderSimulation {
+    // one argument per setting of the generator, as passed by the simulator binary
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         max_orders: u64,
         n_traders: u64,
@@ -350,7 +350,7 @@ impl OrderSimulation {
         let step = max_orders / n_chunks;
         let order_chunks = (0..=max_orders - step)
             .step_by(step as usize)
-            .map(|current| (current..current + step));
+            .map(|current| current..current + step);
 
         let mut tasks = vec![];
 
@@ -389,14 +389,11 @@ async fn run_orders(chunk: Range<u64>, mut simulation: OrderSimulation) -> Resul
 }
 
 fn generate_traders(n_traders: u64) -> Vec<Trader> {
-    let mut traders: Vec<Trader> = Vec::with_capacity(n_traders as usize);
-    for i in 0..traders.capacity() {
-        traders.push(Trader {
-            id: i as u64,
+    (0..n_traders)
+        .map(|_| Trader {
             orders: HashMap::new(),
-        });
-    }
-    traders
+        })
+        .collect()
 }
 
 impl Default for OrderSimulation {